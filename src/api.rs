@@ -0,0 +1,228 @@
+use std::collections::HashMap;
+
+use actix_multipart::Multipart;
+use actix_web::{HttpRequest, HttpResponse, ResponseError, web};
+use serde::{Deserialize, Serialize};
+
+use crate::app::AppData;
+use crate::auth_middleware::AuthenticatedUser;
+use crate::errors::AppError;
+use crate::index::{IndexedFile, UploadRecord, UploadRequest};
+use crate::upload::RequestOptions;
+
+/// Prefix of the versioned JSON api.
+pub const API_PATH: &str = "/api/v1";
+
+/// `AppError` answered with a json body, for api clients.
+#[derive(Debug)]
+pub struct ApiError(AppError);
+
+impl From<AppError> for ApiError {
+    fn from(err: AppError) -> Self {
+        ApiError(err)
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    error: String,
+}
+
+impl ResponseError for ApiError {
+    fn error_response(&self) -> HttpResponse {
+        let status = self.0.error_response().status();
+
+        let error = if status.is_server_error() {
+            "internal error".to_string()
+        } else {
+            self.0.to_string()
+        };
+
+        HttpResponse::build(status).json(ErrorBody { error })
+    }
+}
+
+/// An upload as returned by the api.
+#[derive(Serialize)]
+struct Upload {
+    link: String,
+    url: String,
+    files: Vec<IndexedFile>,
+    size: u64,
+    created: u64,
+    expires_at: Option<u64>,
+    protected: bool,
+    downloads: u64,
+    remaining_downloads: Option<u64>,
+    uploader: Option<String>,
+    message: Option<String>,
+    label: Option<String>,
+}
+
+impl From<UploadRecord> for Upload {
+    fn from(record: UploadRecord) -> Self {
+        Upload {
+            url: format!("/share/{}", record.link),
+            size: record.files.iter().map(|file| file.size).sum(),
+            created: record.metadata.created,
+            expires_at: record.metadata.expires_at,
+            protected: record.metadata.password_hash.is_some(),
+            downloads: record.metadata.downloads,
+            remaining_downloads: record.metadata.remaining_downloads(),
+            uploader: record.metadata.uploader,
+            message: record.metadata.message,
+            label: record.metadata.label,
+            files: record.files,
+            link: record.link,
+        }
+    }
+}
+
+/// An upload request as returned by the api.
+#[derive(Serialize)]
+struct Request {
+    url: String,
+    #[serde(flatten)]
+    request: UploadRequest,
+}
+
+impl From<UploadRequest> for Request {
+    fn from(request: UploadRequest) -> Self {
+        Request { url: format!("/drop/{}", request.token), request }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RequestPath {
+    token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UploadPath {
+    link: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListQuery {
+    owner: Option<String>,
+}
+
+/// `POST /api/v1/uploads`, the same multipart form as the upload page.
+pub async fn upload(req: HttpRequest, payload: Multipart, data: web::Data<AppData>) -> Result<HttpResponse, ApiError> {
+    let manager = data.manager();
+
+    let link = manager.store(payload, AuthenticatedUser::from_request(&req)).await?;
+
+    let upload = Upload::from(manager.get_upload(&link)?);
+
+    Ok(HttpResponse::Created()
+        .header("Location", format!("{}/uploads/{}", API_PATH, link))
+        .json(upload))
+}
+
+/// `GET /api/v1/uploads`, optionally filtered with `?owner=<user>`.
+pub async fn list(query: web::Query<ListQuery>, data: web::Data<AppData>) -> Result<HttpResponse, ApiError> {
+    let owner = query.owner.as_deref().filter(|owner| !owner.is_empty());
+
+    let uploads = data.manager()
+        .list_uploads(owner)?
+        .into_iter()
+        .map(Upload::from)
+        .collect::<Vec<_>>();
+
+    Ok(HttpResponse::Ok().json(uploads))
+}
+
+/// `GET /api/v1/uploads/{link}`, only for uploads of the authenticated user.
+pub async fn get(req: HttpRequest, path: web::Path<UploadPath>, data: web::Data<AppData>) -> Result<HttpResponse, ApiError> {
+    let manager = data.manager();
+    let link = path.into_inner().link;
+    let user = AuthenticatedUser::get(&req);
+
+    let record = web::block(move || manager.owned_upload(&link, user.as_ref())).await.map_err(AppError::from)?;
+
+    let upload = Upload::from(record);
+
+    Ok(HttpResponse::Ok().json(upload))
+}
+
+/// `DELETE /api/v1/uploads/{link}`, only for uploads of the authenticated user.
+pub async fn delete(req: HttpRequest, path: web::Path<UploadPath>, data: web::Data<AppData>) -> Result<HttpResponse, ApiError> {
+    let manager = data.manager();
+    let link = path.into_inner().link;
+    let user = AuthenticatedUser::get(&req);
+
+    web::block(move || manager.delete(&link, user.as_ref())).await.map_err(AppError::from)?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// `GET /api/v1/quota`, storage used by the authenticated user.
+pub async fn quota(req: HttpRequest, data: web::Data<AppData>) -> Result<HttpResponse, ApiError> {
+    let user = AuthenticatedUser::from_request(&req).ok_or(AppError::FileNotFound)?;
+
+    Ok(HttpResponse::Ok().json(data.manager().quota(&user)?))
+}
+
+/// `POST /api/v1/requests`, with optional `expires_in`, `max_size`, `max_files` and `label` form fields.
+pub async fn create_request(req: HttpRequest, form: web::Form<HashMap<String, String>>, data: web::Data<AppData>) -> Result<HttpResponse, ApiError> {
+    let creator = AuthenticatedUser::from_request(&req).ok_or(AppError::FileNotFound)?;
+
+    let request = Request::from(data.manager().create_request(&creator, RequestOptions::from_fields(&form)?)?);
+
+    Ok(HttpResponse::Created().header("Location", request.url.clone()).json(request))
+}
+
+/// `GET /api/v1/requests`, the upload requests of the authenticated user.
+pub async fn list_requests(req: HttpRequest, data: web::Data<AppData>) -> Result<HttpResponse, ApiError> {
+    let creator = AuthenticatedUser::from_request(&req).ok_or(AppError::FileNotFound)?;
+
+    let requests = data.manager()
+        .list_requests(&creator)?
+        .into_iter()
+        .map(Request::from)
+        .collect::<Vec<_>>();
+
+    Ok(HttpResponse::Ok().json(requests))
+}
+
+/// `DELETE /api/v1/requests/{token}`
+pub async fn delete_request(req: HttpRequest, path: web::Path<RequestPath>, data: web::Data<AppData>) -> Result<HttpResponse, ApiError> {
+    let creator = AuthenticatedUser::from_request(&req).ok_or(AppError::FileNotFound)?;
+
+    data.manager().delete_request(&path.token, &creator)?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Json 404 for unknown api paths, instead of the html pages.
+pub async fn not_found() -> Result<HttpResponse, ApiError> {
+    Err(ApiError(AppError::FileNotFound))
+}
+
+#[test]
+fn api_error_hides_internal_errors() {
+    use actix_web::dev::Body;
+    use actix_web::http::StatusCode;
+
+    let body = |response: &HttpResponse| match response.body().as_ref() {
+        Some(Body::Bytes(bytes)) => String::from_utf8_lossy(bytes).to_string(),
+        _ => String::new(),
+    };
+
+    let response = ApiError(AppError::DatabaseError("disk I/O error".to_string())).error_response();
+
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(body(&response), r#"{"error":"internal error"}"#);
+
+    let response = ApiError(AppError::InvalidRequest("Invalid max_downloads: 0".to_string())).error_response();
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert!(body(&response).contains("Invalid max_downloads: 0"));
+}
@@ -4,11 +4,15 @@ use tera::Tera;
 
 use crate::app_configs::ApplicationConfigurations;
 use crate::errors::AppError;
+use crate::tokens::ApiTokens;
+use crate::tus::TusManager;
 use crate::upload::UploadManager;
 
 pub struct AppData {
     templates: Tera,
-    manager: Arc<Box<UploadManager>>,
+    manager: Arc<UploadManager>,
+    tokens: Arc<ApiTokens>,
+    tus: Arc<TusManager>,
 }
 
 impl AppData {
@@ -20,14 +24,19 @@ impl AppData {
         let mut templates = Tera::new(&format!("{}/**/*", templates_directory.display()))?;
         templates.full_reload()?;
 
-        let manager = UploadManager::new(
-            std::path::PathBuf::from(upload_directory),
+        let manager = UploadManager::from_configs(configs.upload_configs())?;
+
+        let tus = TusManager::new(
+            upload_directory.join(".tus"),
             configs.upload_configs().max_size(),
-        );
+            configs.upload_configs().tus_expiry(),
+        )?;
 
         Ok(AppData {
             templates,
-            manager: Arc::new(Box::new(manager)),
+            tokens: Arc::new(ApiTokens::new(manager.index())),
+            manager: Arc::new(manager),
+            tus: Arc::new(tus),
         })
     }
 
@@ -35,7 +44,15 @@ impl AppData {
         &self.templates
     }
 
-    pub fn manager(&self) -> Arc<Box<UploadManager>> {
+    pub fn manager(&self) -> Arc<UploadManager> {
         self.manager.clone()
     }
+
+    pub fn tokens(&self) -> Arc<ApiTokens> {
+        self.tokens.clone()
+    }
+
+    pub fn tus(&self) -> Arc<TusManager> {
+        self.tus.clone()
+    }
 }
@@ -1,30 +1,234 @@
+use std::collections::HashMap;
 use std::net::IpAddr;
 use std::str::FromStr;
 
 use ini::Ini;
 
 use crate::errors::AppError;
+use crate::utils::{IpNetwork, parse_duration, parse_size};
 
-const FSHARE_CONF_FILE_ENV_KEY: &'static str = "FSHARE_CONF_FILE";
+const FSHARE_CONF_FILE_ENV_KEY: &str = "FSHARE_CONF_FILE";
 
 const DEFAULT_NUMBER_OF_THREADS: u16 = 1;
 
 const DEFAULT_MAX_SIZE: usize = 1000000000;
 
-const COMMON_LOG_FORMAT: &'static str = "%a %t \"%r\" %s %b \"%{Referer}i\" \"%{User-Agent}i\" %T \"%{X-Correlation-Id}i\"";
+const DEFAULT_CLEANUP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(3600);
+
+const DEFAULT_TUS_EXPIRY: std::time::Duration = std::time::Duration::from_secs(24 * 3600);
+
+const DEFAULT_SESSION_LIFETIME: std::time::Duration = std::time::Duration::from_secs(7 * 24 * 3600);
+
+const COMMON_LOG_FORMAT: &str = "%a %t \"%r\" %s %b \"%{Referer}i\" \"%{User-Agent}i\" %T \"%{X-Correlation-Id}i\"";
+
+/// Connection settings of an S3 compatible bucket, from the `[storage]` section.
+#[derive(Clone)]
+pub struct S3Configuration {
+    endpoint: Option<String>,
+    region: String,
+    bucket: String,
+    access_key: String,
+    secret_key: String,
+    prefix: String,
+    path_style: bool,
+}
+
+impl std::fmt::Debug for S3Configuration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("S3Configuration")
+            .field("endpoint", &self.endpoint)
+            .field("region", &self.region)
+            .field("bucket", &self.bucket)
+            .field("access_key", &self.access_key)
+            .field("prefix", &self.prefix)
+            .field("path_style", &self.path_style)
+            .finish()
+    }
+}
+
+impl S3Configuration {
+    pub fn endpoint(&self) -> Option<&str> {
+        self.endpoint.as_deref()
+    }
+
+    pub fn region(&self) -> &str {
+        &self.region
+    }
+
+    pub fn bucket(&self) -> &str {
+        &self.bucket
+    }
+
+    pub fn access_key(&self) -> &str {
+        &self.access_key
+    }
+
+    pub fn secret_key(&self) -> &str {
+        &self.secret_key
+    }
+
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    pub fn path_style(&self) -> bool {
+        self.path_style
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum StorageStrategy {
+    Local,
+    S3(S3Configuration),
+}
+
+impl StorageStrategy {
+    fn section_name() -> &'static str {
+        "storage"
+    }
+
+    pub fn try_from(configs: &Ini) -> Result<Self, AppError> {
+        let properties = match configs.section(Some(Self::section_name())) {
+            Some(properties) => properties,
+            None => return Ok(StorageStrategy::Local),
+        };
+
+        let required = |key: &str| properties.get(key)
+            .map(|value| value.to_string())
+            .ok_or(AppError::InitError(format!("Missing {} from section: [{}]", key, Self::section_name())));
+
+        match properties.get("backend").unwrap_or("local") {
+            "local" => Ok(StorageStrategy::Local),
+            "s3" => Ok(StorageStrategy::S3(S3Configuration {
+                endpoint: properties.get("endpoint").map(|endpoint| endpoint.to_string()),
+                region: properties.get("region").unwrap_or("us-east-1").to_string(),
+                bucket: required("bucket")?,
+                access_key: required("access_key")?,
+                secret_key: required("secret_key")?,
+                prefix: properties.get("prefix").unwrap_or("").to_string(),
+                path_style: properties.get("path_style")
+                    .map(|value| value.eq("true"))
+                    .unwrap_or(false),
+            })),
+            other => Err(AppError::InitError(format!("Invalid storage backend: {}", other))),
+        }
+    }
+}
+
+/// How files are compressed inside the stored archives.
+#[derive(Clone, Debug)]
+pub struct CompressionConfiguration {
+    method: zip::CompressionMethod,
+    level: Option<i32>,
+    skip_compressed: bool,
+}
+
+impl CompressionConfiguration {
+    fn try_from(configs: &Ini) -> Result<Self, AppError> {
+        let properties = configs
+            .section(Some(UploadConfiguration::section_name()))
+            .ok_or(AppError::InitError(format!("Section [{}] is missing from configuration file", UploadConfiguration::section_name())))?;
+
+        let method = match properties.get("compression").unwrap_or("stored") {
+            "stored" => zip::CompressionMethod::Stored,
+            "deflate" => zip::CompressionMethod::Deflated,
+            "bzip2" => zip::CompressionMethod::Bzip2,
+            "zstd" => zip::CompressionMethod::Zstd,
+            other => return Err(AppError::InitError(format!("Invalid compression: {}", other))),
+        };
+
+        let level = properties.get("compression_level")
+            .map(|level_str| level_str.parse::<i32>()
+                .map_err(|_| AppError::InitError(format!("Invalid compression_level: {}", level_str))))
+            .transpose()?;
+
+        // The zip writer only checks the level when a file is added, fail at startup instead.
+        zip::ZipWriter::new(std::io::Cursor::new(Vec::new()))
+            .start_file("check", zip::write::FileOptions::default().compression_method(method).compression_level(level))
+            .map_err(|e| AppError::InitError(format!("Invalid compression_level {:?} for {:?}: {}", level, method, e)))?;
+
+        let skip_compressed = properties.get("skip_compressed")
+            .map(|value| value.eq("true"))
+            .unwrap_or(true);
+
+        Ok(CompressionConfiguration { method, level, skip_compressed })
+    }
+
+    pub fn method(&self) -> zip::CompressionMethod {
+        self.method
+    }
+
+    pub fn level(&self) -> Option<i32> {
+        self.level
+    }
+
+    /// Whether files that are already compressed (images, videos, archives) are stored as they are.
+    pub fn skip_compressed(&self) -> bool {
+        self.skip_compressed
+    }
+}
+
+/// How much each user may keep stored: `quota` of the `[upload]` section, overridden per user in `[quotas]`.
+#[derive(Clone, Debug, Default)]
+pub struct QuotaConfiguration {
+    default: Option<u64>,
+    users: HashMap<String, Option<u64>>,
+}
+
+impl QuotaConfiguration {
+    fn section_name() -> &'static str {
+        "quotas"
+    }
+
+    fn try_from(configs: &Ini) -> Result<Self, AppError> {
+        let default = configs.get_from(Some(UploadConfiguration::section_name()), "quota")
+            .map(parse_size)
+            .transpose()
+            .map_err(|e| AppError::InitError(format!("Invalid quota in [{}]: {}", UploadConfiguration::section_name(), e)))?
+            .flatten();
+
+        let mut users = HashMap::new();
+
+        if let Some(properties) = configs.section(Some(Self::section_name())) {
+            for (user, size) in properties.iter() {
+                let quota = parse_size(size)
+                    .map_err(|e| AppError::InitError(format!("Invalid quota for {} in [{}]: {}", user, Self::section_name(), e)))?;
+
+                users.insert(user.to_string(), quota);
+            }
+        }
+
+        Ok(QuotaConfiguration { default, users })
+    }
+
+    /// Bytes `user` may keep stored, `None` when unlimited.
+    pub fn quota(&self, user: &str) -> Option<u64> {
+        self.users.get(user).copied().unwrap_or(self.default)
+    }
+}
 
 #[derive(Clone, Debug)]
 pub struct UploadConfiguration {
     max_size: usize,
     upload_directory: std::path::PathBuf,
+    default_expiry: Option<std::time::Duration>,
+    cleanup_interval: std::time::Duration,
+    tus_expiry: Option<std::time::Duration>,
+    index_file: std::path::PathBuf,
+    state_file: std::path::PathBuf,
+    require_auth: bool,
+    storage: StorageStrategy,
+    compression: CompressionConfiguration,
+    quotas: QuotaConfiguration,
+    dedupe: bool,
 }
 
 impl UploadConfiguration {
     fn section_name() -> &'static str {
-        return "upload";
+        "upload"
     }
 
-    fn try_from(configs: &Ini) -> Result<Self, AppError> {
+    pub fn try_from(configs: &Ini) -> Result<Self, AppError> {
         let properties = configs
             .section(Some(Self::section_name()))
             .ok_or(AppError::InitError(format!("Section [{}] is missing from configuration file", Self::section_name())))?;
@@ -34,8 +238,43 @@ impl UploadConfiguration {
             .unwrap_or(DEFAULT_MAX_SIZE);
 
         let upload_directory = properties.get("upload_directory")
-            .map(|upload| std::path::PathBuf::from(upload))
-            .unwrap_or(std::env::temp_dir());
+            .map(std::path::PathBuf::from)
+            .unwrap_or_else(std::env::temp_dir);
+
+        let default_expiry = properties.get("default_expiry")
+            .map(parse_duration)
+            .transpose()
+            .map_err(|e| AppError::InitError(format!("Invalid default_expiry in [{}]: {}", Self::section_name(), e)))?
+            .flatten();
+
+        let cleanup_interval = properties.get("cleanup_interval")
+            .map(parse_duration)
+            .transpose()
+            .map_err(|e| AppError::InitError(format!("Invalid cleanup_interval in [{}]: {}", Self::section_name(), e)))?
+            .flatten()
+            .unwrap_or(DEFAULT_CLEANUP_INTERVAL);
+
+        let tus_expiry = properties.get("tus_expiry")
+            .map(parse_duration)
+            .transpose()
+            .map_err(|e| AppError::InitError(format!("Invalid tus_expiry in [{}]: {}", Self::section_name(), e)))?
+            .unwrap_or(Some(DEFAULT_TUS_EXPIRY));
+
+        let index_file = properties.get("index_file")
+            .map(std::path::PathBuf::from)
+            .unwrap_or_else(|| upload_directory.join(".index.sqlite"));
+
+        let state_file = properties.get("state_file")
+            .map(std::path::PathBuf::from)
+            .unwrap_or_else(|| upload_directory.join(".state.sqlite"));
+
+        let require_auth = properties.get("require_auth")
+            .map(|value| value.eq("true"))
+            .unwrap_or(false);
+
+        let dedupe = properties.get("dedupe")
+            .map(|value| value.eq("true"))
+            .unwrap_or(false);
 
         if !upload_directory.exists() {
             std::fs::create_dir_all(&upload_directory)
@@ -49,13 +288,23 @@ impl UploadConfiguration {
 
 
             if read_only {
-                return Err(AppError::InitError(format!("Upload directory without write permissions")));
+                return Err(AppError::InitError("Upload directory without write permissions".to_string()));
             }
         }
 
         Ok(UploadConfiguration {
             max_size: max_upload_size,
             upload_directory,
+            default_expiry,
+            cleanup_interval,
+            tus_expiry,
+            index_file,
+            state_file,
+            require_auth,
+            storage: StorageStrategy::try_from(configs)?,
+            compression: CompressionConfiguration::try_from(configs)?,
+            quotas: QuotaConfiguration::try_from(configs)?,
+            dedupe,
         })
     }
 
@@ -65,6 +314,48 @@ impl UploadConfiguration {
     pub fn upload_directory(&self) -> &std::path::PathBuf {
         &self.upload_directory
     }
+
+    pub fn default_expiry(&self) -> Option<std::time::Duration> {
+        self.default_expiry
+    }
+
+    pub fn cleanup_interval(&self) -> std::time::Duration {
+        self.cleanup_interval
+    }
+
+    /// How long an unfinished resumable upload is kept after its last chunk.
+    pub fn tus_expiry(&self) -> Option<std::time::Duration> {
+        self.tus_expiry
+    }
+
+    pub fn index_file(&self) -> &std::path::PathBuf {
+        &self.index_file
+    }
+
+    pub fn state_file(&self) -> &std::path::PathBuf {
+        &self.state_file
+    }
+
+    pub fn require_auth(&self) -> bool {
+        self.require_auth
+    }
+
+    pub fn storage(&self) -> &StorageStrategy {
+        &self.storage
+    }
+
+    pub fn compression(&self) -> &CompressionConfiguration {
+        &self.compression
+    }
+
+    pub fn quotas(&self) -> &QuotaConfiguration {
+        &self.quotas
+    }
+
+    /// Whether uploaded files are stored once by content, shared by every upload sending them.
+    pub fn dedupe(&self) -> bool {
+        self.dedupe
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -74,7 +365,7 @@ pub struct UiConfiguration {
 
 impl UiConfiguration {
     fn section_name() -> &'static str {
-        return "ui";
+        "ui"
     }
 
     fn try_from(configs: &Ini) -> Result<Self, AppError> {
@@ -84,11 +375,11 @@ impl UiConfiguration {
 
 
         let tera_directory = properties.get("tera_templates")
-            .map(|dir| std::path::PathBuf::from(dir))
+            .map(std::path::PathBuf::from)
             .ok_or(AppError::InitError(format!("Missing tera_templates from section: [{}]", Self::section_name())))?;
 
         if !tera_directory.exists() {
-            return Err(AppError::InitError(format!("Tera templates directory not exists")));
+            return Err(AppError::InitError("Tera templates directory not exists".to_string()));
         }
 
         Ok(UiConfiguration {
@@ -103,9 +394,14 @@ impl UiConfiguration {
 
 #[derive(Clone, Debug)]
 pub enum AuthStrategy {
-    File(std::path::PathBuf),
+    File { users_file: std::path::PathBuf, allow_plaintext: bool },
     #[cfg(target_os = "linux")]
     PamModule(String),
+    Ldap(LdapConfiguration),
+    /// Single sign-on only, passwords are not accepted.
+    Oidc(OidcConfiguration),
+    /// Users authenticated by a reverse proxy, passwords are not accepted.
+    Proxy(ProxyConfiguration),
 }
 
 impl AuthStrategy {
@@ -119,16 +415,26 @@ impl AuthStrategy {
 
         if auth_strategy.eq("auth_file") {
             let users_file = configs.get_from(Some(auth_strategy), "user_pass_file")
-                .map(|path| std::path::Path::new(path))
+                .map(std::path::Path::new)
                 .unwrap_or(std::path::Path::new("users.txt"));
 
             if !users_file.exists() {
                 return Err(AppError::InitError(format!("Unable to read users password file: {}", users_file.display())));
             }
 
-            Ok(AuthStrategy::File(users_file.to_path_buf()))
+            let allow_plaintext = configs.get_from(Some(auth_strategy), "allow_plaintext")
+                .map(|value| value.eq("true"))
+                .unwrap_or(false);
+
+            Ok(AuthStrategy::File { users_file: users_file.to_path_buf(), allow_plaintext })
+        } else if auth_strategy.eq(LdapConfiguration::section_name()) {
+            Ok(AuthStrategy::Ldap(LdapConfiguration::try_from(configs)?))
+        } else if auth_strategy.eq(OidcConfiguration::section_name()) {
+            Ok(AuthStrategy::Oidc(OidcConfiguration::try_from(configs)?))
+        } else if auth_strategy.eq(ProxyConfiguration::section_name()) {
+            Ok(AuthStrategy::Proxy(ProxyConfiguration::try_from(configs)?))
         } else {
-            Err(AppError::InitError(format!("Invalid auth_strategy")))
+            Err(AppError::InitError("Invalid auth_strategy".to_string()))
         }
     }
 
@@ -141,14 +447,24 @@ impl AuthStrategy {
 
         if auth_strategy.eq("auth_file") {
             let users_file = configs.get_from(Some(auth_strategy), "user_pass_file")
-                .map(|path| std::path::Path::new(path))
+                .map(std::path::Path::new)
                 .unwrap_or(std::path::Path::new("users.txt"));
 
             if !users_file.exists() {
                 return Err(AppError::InitError(format!("Unable to read users password file: {}", users_file.display())));
             }
 
-            Ok(AuthStrategy::File(users_file.to_path_buf()))
+            let allow_plaintext = configs.get_from(Some(auth_strategy), "allow_plaintext")
+                .map(|value| value.eq("true"))
+                .unwrap_or(false);
+
+            Ok(AuthStrategy::File { users_file: users_file.to_path_buf(), allow_plaintext })
+        } else if auth_strategy.eq(LdapConfiguration::section_name()) {
+            Ok(AuthStrategy::Ldap(LdapConfiguration::try_from(configs)?))
+        } else if auth_strategy.eq(OidcConfiguration::section_name()) {
+            Ok(AuthStrategy::Oidc(OidcConfiguration::try_from(configs)?))
+        } else if auth_strategy.eq(ProxyConfiguration::section_name()) {
+            Ok(AuthStrategy::Proxy(ProxyConfiguration::try_from(configs)?))
         } else {
             let pam_module = configs.get_from(Some(auth_strategy), "pam_module_name")
                 .ok_or(AppError::InitError(format!("Missing pam module name in section:[{}]", auth_strategy)))?;
@@ -158,6 +474,470 @@ impl AuthStrategy {
     }
 }
 
+/// How `auth_ldap` finds the entry of a user to bind as.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LdapUserLookup {
+    /// Binds with the dn of a template such as `uid={username},ou=people,dc=example,dc=org`.
+    Template(String),
+    /// Searches `base_dn` with `user_filter`, then binds with the dn found.
+    Search { base_dn: String, user_filter: String },
+}
+
+/// Directory server checking the passwords, from the `[auth_ldap]` section.
+#[derive(Clone)]
+pub struct LdapConfiguration {
+    url: String,
+    starttls: bool,
+    tls_verify: bool,
+    timeout: std::time::Duration,
+    bind_dn: Option<String>,
+    bind_password: Option<String>,
+    lookup: LdapUserLookup,
+    required_group: Option<String>,
+    group_filter: String,
+}
+
+impl std::fmt::Debug for LdapConfiguration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LdapConfiguration")
+            .field("url", &self.url)
+            .field("starttls", &self.starttls)
+            .field("tls_verify", &self.tls_verify)
+            .field("bind_dn", &self.bind_dn)
+            .field("lookup", &self.lookup)
+            .field("required_group", &self.required_group)
+            .finish()
+    }
+}
+
+impl LdapConfiguration {
+    fn section_name() -> &'static str {
+        "auth_ldap"
+    }
+
+    pub fn try_from(configs: &Ini) -> Result<Self, AppError> {
+        let get = |key: &str| configs.get_from(Some(Self::section_name()), key)
+            .map(|value| value.trim())
+            .filter(|value| !value.is_empty());
+
+        let url = get("url")
+            .ok_or(AppError::InitError(format!("Missing url from section: [{}]", Self::section_name())))?;
+
+        if !url.starts_with("ldap://") && !url.starts_with("ldaps://") {
+            return Err(AppError::InitError(format!("Invalid url in [{}], expected ldap:// or ldaps://: {}", Self::section_name(), url)));
+        }
+
+        let starttls = get("starttls").map(|value| value.eq("true")).unwrap_or(false);
+
+        if starttls && url.starts_with("ldaps://") {
+            return Err(AppError::InitError(format!("starttls cannot be used with an ldaps:// url in [{}]", Self::section_name())));
+        }
+
+        let timeout = get("timeout")
+            .map(|value| parse_duration(value)
+                .ok()
+                .flatten()
+                .ok_or_else(|| AppError::InitError(format!("Invalid timeout in [{}]: {}", Self::section_name(), value))))
+            .transpose()?
+            .unwrap_or(std::time::Duration::from_secs(5));
+
+        let lookup = match (get("user_dn"), get("base_dn")) {
+            (Some(user_dn), None) if user_dn.contains("{username}") => LdapUserLookup::Template(user_dn.to_string()),
+            (None, Some(base_dn)) => LdapUserLookup::Search {
+                base_dn: base_dn.to_string(),
+                user_filter: get("user_filter").unwrap_or("(uid={username})").to_string(),
+            },
+            _ => return Err(AppError::InitError(format!(
+                "Either user_dn (with a {{username}} placeholder) or base_dn must be set in [{}]", Self::section_name()
+            ))),
+        };
+
+        Ok(LdapConfiguration {
+            url: url.to_string(),
+            starttls,
+            tls_verify: get("tls_verify").map(|value| value.eq("true")).unwrap_or(true),
+            timeout,
+            bind_dn: get("bind_dn").map(|value| value.to_string()),
+            bind_password: get("bind_password").map(|value| value.to_string()),
+            lookup,
+            required_group: get("required_group").map(|value| value.to_string()),
+            group_filter: get("group_filter")
+                .unwrap_or("(|(member={dn})(uniqueMember={dn})(memberUid={username}))")
+                .to_string(),
+        })
+    }
+
+    /// `ldap://` or `ldaps://` url of the server.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Whether `ldap://` connections are upgraded to TLS before binding.
+    pub fn starttls(&self) -> bool {
+        self.starttls
+    }
+
+    pub fn tls_verify(&self) -> bool {
+        self.tls_verify
+    }
+
+    pub fn timeout(&self) -> std::time::Duration {
+        self.timeout
+    }
+
+    /// Account searching users and groups, anonymous when missing.
+    pub fn bind_dn(&self) -> Option<&str> {
+        self.bind_dn.as_deref()
+    }
+
+    pub fn bind_password(&self) -> Option<&str> {
+        self.bind_password.as_deref()
+    }
+
+    pub fn lookup(&self) -> &LdapUserLookup {
+        &self.lookup
+    }
+
+    /// Dn of a group users must belong to.
+    pub fn required_group(&self) -> Option<&str> {
+        self.required_group.as_deref()
+    }
+
+    /// Filter matching the required group when the user is a member, with `{dn}` and `{username}` placeholders.
+    pub fn group_filter(&self) -> &str {
+        &self.group_filter
+    }
+}
+
+/// OpenID Connect provider users log in with, from the `[auth_oidc]` section.
+#[derive(Clone)]
+pub struct OidcConfiguration {
+    issuer: String,
+    client_id: String,
+    client_secret: Option<String>,
+    redirect_url: String,
+    scopes: String,
+    username_claim: String,
+    groups_claim: String,
+    allowed_groups: Vec<String>,
+    admin_groups: Vec<String>,
+    name: String,
+}
+
+impl std::fmt::Debug for OidcConfiguration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OidcConfiguration")
+            .field("issuer", &self.issuer)
+            .field("client_id", &self.client_id)
+            .field("redirect_url", &self.redirect_url)
+            .field("scopes", &self.scopes)
+            .field("username_claim", &self.username_claim)
+            .field("groups_claim", &self.groups_claim)
+            .field("allowed_groups", &self.allowed_groups)
+            .field("admin_groups", &self.admin_groups)
+            .finish()
+    }
+}
+
+impl OidcConfiguration {
+    fn section_name() -> &'static str {
+        "auth_oidc"
+    }
+
+    pub fn try_from(configs: &Ini) -> Result<Self, AppError> {
+        let get = |key: &str| configs.get_from(Some(Self::section_name()), key)
+            .map(|value| value.trim())
+            .filter(|value| !value.is_empty());
+
+        let required = |key: &str| get(key)
+            .map(|value| value.to_string())
+            .ok_or(AppError::InitError(format!("Missing {} from section: [{}]", key, Self::section_name())));
+
+        let issuer = required("issuer")?.trim_end_matches('/').to_string();
+        let redirect_url = required("redirect_url")?;
+
+        for (key, url) in [("issuer", &issuer), ("redirect_url", &redirect_url)] {
+            if !url.starts_with("https://") && !url.starts_with("http://") {
+                return Err(AppError::InitError(format!("Invalid {} in [{}]: {}", key, Self::section_name(), url)));
+            }
+        }
+
+        let scopes = get("scopes").unwrap_or("openid profile email");
+
+        if !scopes.split_whitespace().any(|scope| scope == "openid") {
+            return Err(AppError::InitError(format!("scopes must include openid in [{}]", Self::section_name())));
+        }
+
+        let groups = |key: &str| get(key)
+            .unwrap_or("")
+            .split(',')
+            .map(|group| group.trim())
+            .filter(|group| !group.is_empty())
+            .map(|group| group.to_string())
+            .collect();
+
+        Ok(OidcConfiguration {
+            issuer,
+            client_id: required("client_id")?,
+            client_secret: get("client_secret").map(|value| value.to_string()),
+            redirect_url,
+            scopes: scopes.to_string(),
+            username_claim: get("username_claim").unwrap_or("preferred_username").to_string(),
+            groups_claim: get("groups_claim").unwrap_or("groups").to_string(),
+            allowed_groups: groups("allowed_groups"),
+            admin_groups: groups("admin_groups"),
+            name: get("name").unwrap_or("single sign-on").to_string(),
+        })
+    }
+
+    /// Issuer url, without a trailing slash, its configuration is discovered from.
+    pub fn issuer(&self) -> &str {
+        &self.issuer
+    }
+
+    pub fn client_id(&self) -> &str {
+        &self.client_id
+    }
+
+    /// Secret of confidential clients, public ones rely on PKCE alone.
+    pub fn client_secret(&self) -> Option<&str> {
+        self.client_secret.as_deref()
+    }
+
+    /// Url of `/login/oidc/callback` registered with the provider.
+    pub fn redirect_url(&self) -> &str {
+        &self.redirect_url
+    }
+
+    pub fn scopes(&self) -> &str {
+        &self.scopes
+    }
+
+    /// Claim holding the fshare user name.
+    pub fn username_claim(&self) -> &str {
+        &self.username_claim
+    }
+
+    pub fn groups_claim(&self) -> &str {
+        &self.groups_claim
+    }
+
+    /// Groups allowed to log in, anyone the provider knows when empty.
+    pub fn allowed_groups(&self) -> &[String] {
+        &self.allowed_groups
+    }
+
+    /// Whether logins depend on the groups of the user.
+    pub fn uses_groups(&self) -> bool {
+        !self.allowed_groups.is_empty() || !self.admin_groups.is_empty()
+    }
+
+    /// Groups whose members log in as admins, allowed in even when missing from `allowed_groups`.
+    pub fn admin_groups(&self) -> &[String] {
+        &self.admin_groups
+    }
+
+    /// Provider name shown on the login button.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Authenticating reverse proxy sending the user in a header, from the `[auth_proxy]` section.
+#[derive(Clone, Debug)]
+pub struct ProxyConfiguration {
+    header: String,
+    trusted_proxies: Vec<IpNetwork>,
+}
+
+impl ProxyConfiguration {
+    fn section_name() -> &'static str {
+        "auth_proxy"
+    }
+
+    pub fn try_from(configs: &Ini) -> Result<Self, AppError> {
+        let header = configs.get_from(Some(Self::section_name()), "header")
+            .map(|header| header.trim())
+            .filter(|header| !header.is_empty())
+            .unwrap_or("X-Forwarded-User");
+
+        if actix_web::http::HeaderName::from_str(header).is_err() {
+            return Err(AppError::InitError(format!("Invalid header in [{}]: {}", Self::section_name(), header)));
+        }
+
+        let trusted_proxies = configs.get_from(Some(Self::section_name()), "trusted_proxies")
+            .unwrap_or("")
+            .split(',')
+            .filter(|network| !network.trim().is_empty())
+            .map(|network| IpNetwork::from_str(network)
+                .map_err(|e| AppError::InitError(format!("{} in [{}]", e, Self::section_name()))))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if trusted_proxies.is_empty() {
+            return Err(AppError::InitError(format!("Missing trusted_proxies from section: [{}]", Self::section_name())));
+        }
+
+        Ok(ProxyConfiguration { header: header.to_string(), trusted_proxies })
+    }
+
+    /// Header holding the name of the user, e.g. `X-Forwarded-User`.
+    pub fn header(&self) -> &str {
+        &self.header
+    }
+
+    /// Whether requests from `address` come from the proxy, the only ones whose header is believed.
+    pub fn is_trusted(&self, address: &IpAddr) -> bool {
+        let address = address.to_canonical();
+
+        self.trusted_proxies.iter().any(|network| network.contains(&address))
+    }
+}
+
+/// Cookie sessions of the login page, from the `[session]` section.
+#[derive(Clone)]
+pub struct SessionConfiguration {
+    secret: Option<String>,
+    lifetime: std::time::Duration,
+}
+
+impl std::fmt::Debug for SessionConfiguration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SessionConfiguration")
+            .field("lifetime", &self.lifetime)
+            .finish()
+    }
+}
+
+impl SessionConfiguration {
+    fn section_name() -> &'static str {
+        "session"
+    }
+
+    pub fn try_from(configs: &Ini) -> Result<Self, AppError> {
+        let secret = configs.get_from(Some(Self::section_name()), "secret")
+            .filter(|secret| !secret.is_empty())
+            .map(|secret| secret.to_string());
+
+        let lifetime = configs.get_from(Some(Self::section_name()), "lifetime")
+            .map(|value| parse_duration(value)
+                .ok()
+                .flatten()
+                .ok_or_else(|| AppError::InitError(format!("Invalid lifetime in [{}]: {}", Self::section_name(), value))))
+            .transpose()?
+            .unwrap_or(DEFAULT_SESSION_LIFETIME);
+
+        Ok(SessionConfiguration { secret, lifetime })
+    }
+
+    /// Key signing the session cookies, a random one for each run when missing.
+    pub fn secret(&self) -> Option<&str> {
+        self.secret.as_deref()
+    }
+
+    pub fn lifetime(&self) -> std::time::Duration {
+        self.lifetime
+    }
+}
+
+/// PEM files used to serve https, re-read on SIGHUP.
+#[derive(Clone, Debug)]
+pub struct TlsConfiguration {
+    certificate: std::path::PathBuf,
+    private_key: std::path::PathBuf,
+}
+
+impl TlsConfiguration {
+    pub fn certificate(&self) -> &std::path::PathBuf {
+        &self.certificate
+    }
+
+    pub fn private_key(&self) -> &std::path::PathBuf {
+        &self.private_key
+    }
+}
+
+/// Lockout of clients failing to log in, from the `[auth_throttle]` section.
+#[derive(Clone, Debug)]
+pub struct ThrottleConfiguration {
+    enabled: bool,
+    free_attempts: u32,
+    base_lockout: std::time::Duration,
+    max_lockout: std::time::Duration,
+    reset_after: std::time::Duration,
+    trusted_networks: Vec<IpNetwork>,
+}
+
+impl ThrottleConfiguration {
+    fn section_name() -> &'static str {
+        "auth_throttle"
+    }
+
+    pub fn try_from(configs: &Ini) -> Result<Self, AppError> {
+        let get = |key: &str| configs.get_from(Some(Self::section_name()), key);
+
+        let duration = |key: &str, default: std::time::Duration| -> Result<std::time::Duration, AppError> {
+            get(key)
+                .map(|value| parse_duration(value)
+                    .ok()
+                    .flatten()
+                    .ok_or_else(|| AppError::InitError(format!("Invalid {} in [{}]: {}", key, Self::section_name(), value))))
+                .transpose()
+                .map(|value| value.unwrap_or(default))
+        };
+
+        let free_attempts = get("free_attempts")
+            .map(|value| value.parse::<u32>()
+                .map_err(|_| AppError::InitError(format!("Invalid free_attempts in [{}]: {}", Self::section_name(), value))))
+            .transpose()?
+            .unwrap_or(5);
+
+        let trusted_networks = get("trusted_networks")
+            .unwrap_or("")
+            .split(',')
+            .filter(|network| !network.trim().is_empty())
+            .map(|network| IpNetwork::from_str(network)
+                .map_err(|e| AppError::InitError(format!("{} in [{}]", e, Self::section_name()))))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(ThrottleConfiguration {
+            enabled: get("enabled").map(|value| value.eq("true")).unwrap_or(true),
+            free_attempts,
+            base_lockout: duration("base_lockout", std::time::Duration::from_secs(1))?,
+            max_lockout: duration("max_lockout", std::time::Duration::from_secs(15 * 60))?,
+            reset_after: duration("reset_after", std::time::Duration::from_secs(3600))?,
+            trusted_networks,
+        })
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Failed logins allowed before the first lockout.
+    pub fn free_attempts(&self) -> u32 {
+        self.free_attempts
+    }
+
+    /// Length of the first lockout, doubled by every failure after it.
+    pub fn base_lockout(&self) -> std::time::Duration {
+        self.base_lockout
+    }
+
+    pub fn max_lockout(&self) -> std::time::Duration {
+        self.max_lockout
+    }
+
+    /// How long failures are remembered after the last one.
+    pub fn reset_after(&self) -> std::time::Duration {
+        self.reset_after
+    }
+
+    /// Clients that are never locked out.
+    pub fn trusted_networks(&self) -> &[IpNetwork] {
+        &self.trusted_networks
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct ServerConfiguration {
     host: IpAddr,
@@ -165,11 +945,16 @@ pub struct ServerConfiguration {
     number_thread: u16,
     auth_strategy: AuthStrategy,
     log_format: String,
+    tls: Option<TlsConfiguration>,
+    http_redirect_port: Option<u16>,
+    throttle: ThrottleConfiguration,
+    session: SessionConfiguration,
+    admins: Vec<String>,
 }
 
 impl ServerConfiguration {
     fn section_name() -> &'static str {
-        return "server";
+        "server"
     }
 
 
@@ -196,12 +981,43 @@ impl ServerConfiguration {
             .unwrap_or(COMMON_LOG_FORMAT)
             .to_string();
 
+        let tls = match (properties.get("tls_certificate"), properties.get("tls_private_key")) {
+            (Some(certificate), Some(private_key)) => Some(TlsConfiguration {
+                certificate: std::path::PathBuf::from(certificate),
+                private_key: std::path::PathBuf::from(private_key),
+            }),
+            (None, None) => None,
+            _ => return Err(AppError::InitError(format!("Both tls_certificate and tls_private_key must be set in [{}]", Self::section_name()))),
+        };
+
+        let http_redirect_port = properties.get("http_redirect_port")
+            .map(|port_str| port_str.parse::<u16>()
+                .map_err(|_| AppError::InitError(format!("Invalid http_redirect_port in [{}]", Self::section_name()))))
+            .transpose()?;
+
+        if http_redirect_port.is_some() && tls.is_none() {
+            return Err(AppError::InitError("http_redirect_port requires tls_certificate and tls_private_key".to_string()));
+        }
+
+        let admins = properties.get("admins")
+            .unwrap_or("")
+            .split(',')
+            .map(|user| user.trim())
+            .filter(|user| !user.is_empty())
+            .map(|user| user.to_string())
+            .collect();
+
         Ok(ServerConfiguration {
             host,
             port,
             number_thread: workers,
             auth_strategy,
             log_format,
+            tls,
+            http_redirect_port,
+            throttle: ThrottleConfiguration::try_from(configs)?,
+            session: SessionConfiguration::try_from(configs)?,
+            admins,
         })
     }
 
@@ -224,6 +1040,27 @@ impl ServerConfiguration {
     pub fn log_format(&self) -> &str {
         &self.log_format
     }
+
+    pub fn tls(&self) -> Option<&TlsConfiguration> {
+        self.tls.as_ref()
+    }
+
+    pub fn http_redirect_port(&self) -> Option<u16> {
+        self.http_redirect_port
+    }
+
+    pub fn throttle(&self) -> &ThrottleConfiguration {
+        &self.throttle
+    }
+
+    pub fn session(&self) -> &SessionConfiguration {
+        &self.session
+    }
+
+    /// Users who manage every upload, whatever the authentication strategy.
+    pub fn admins(&self) -> &[String] {
+        &self.admins
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -239,19 +1076,19 @@ impl ApplicationConfigurations {
 
         if let Some(config_file) = some_conf_file {
             configs = Ini::load_from_file(config_file)
-                .expect(
-                    format!("Could not read config file: {}. You can set environment variable (FSHARE_CONF_FILE) with the filename or pass by argument",
-                            config_file).as_str())
+                .map_err(|e| AppError::InitError(
+                    format!("Could not read config file: {}: {}. You can set environment variable (FSHARE_CONF_FILE) with the filename or pass by argument",
+                            config_file, e)))?;
         } else {
             let config_file = std::env::var(FSHARE_CONF_FILE_ENV_KEY)
                 .ok()
                 .map(|d| std::path::Path::new(&d).to_path_buf())
-                .unwrap_or(std::path::Path::new("config.ini").to_path_buf());
+                .unwrap_or_else(|| std::path::Path::new("config.ini").to_path_buf());
 
             configs = Ini::load_from_file(&config_file)
-                .expect(
-                    format!("Could not read config file: {}. You can set environment variable (FSHARE_CONF_FILE) with the filename or pass by argument",
-                            &config_file.display()).as_str());
+                .map_err(|e| AppError::InitError(
+                    format!("Could not read config file: {}: {}. You can set environment variable (FSHARE_CONF_FILE) with the filename or pass by argument",
+                            &config_file.display(), e)))?;
         }
 
         Ok(ApplicationConfigurations {
@@ -1,33 +1,215 @@
-use std::sync::Arc;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
-use actix_web::Error;
+use actix_web::{Error, HttpMessage, HttpRequest, web};
+use actix_web::http::{header, Method};
 use actix_web::HttpResponse;
 use base64::Engine;
-use futures::future::{Either, ok, Ready};
+use futures::future::{LocalBoxFuture, ok, Ready};
 use futures::task::{Context, Poll};
+use sha2::{Digest, Sha256};
 
-use crate::app_configs::AuthStrategy;
+use crate::app_configs::{AuthStrategy, ProxyConfiguration, ServerConfiguration};
 use crate::authenticator::{Authenticator, get_authenticator};
 use crate::errors::AppError;
+use crate::oidc::OidcClient;
+use crate::session::{SESSION_COOKIE, SessionManager};
+use crate::throttle::LoginThrottle;
+use crate::tokens::{ApiTokens, token_id};
+use crate::utils::percent_encode;
 
+/// What a user may do: admins manage every upload, users only their own.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    User,
+    Admin,
+}
+
+/// User authenticated by `BasicAuthMiddleware`, stored in the request extensions.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AuthenticatedUser {
+    pub name: String,
+    pub role: Role,
+}
+
+impl AuthenticatedUser {
+    pub fn new(name: String, role: Role) -> Self {
+        AuthenticatedUser { name, role }
+    }
+
+    /// Name of the authenticated user.
+    pub fn from_request(req: &HttpRequest) -> Option<String> {
+        Self::get(req).map(|user| user.name)
+    }
+
+    /// The authenticated user, with their role.
+    pub fn get(req: &HttpRequest) -> Option<AuthenticatedUser> {
+        req.extensions().get::<AuthenticatedUser>().cloned()
+    }
+
+    pub fn is_admin(&self) -> bool {
+        self.role == Role::Admin
+    }
+}
+
+/// How long a successful password check is remembered, sparing a directory bind or hash per request.
+const VERIFIED_CREDENTIALS_TTL: Duration = Duration::from_secs(60);
+
+/// Credentials checked recently, keyed by a salted hash so the passwords are not kept in memory.
+struct VerifiedCredentials {
+    salt: [u8; 32],
+    entries: Mutex<HashMap<[u8; 32], Instant>>,
+}
+
+impl VerifiedCredentials {
+    fn new() -> Self {
+        VerifiedCredentials { salt: rand::random(), entries: Mutex::new(HashMap::new()) }
+    }
+
+    fn key(&self, username: &str, password: &str) -> [u8; 32] {
+        Sha256::new()
+            .chain_update(self.salt)
+            .chain_update(username)
+            .chain_update([0])
+            .chain_update(password)
+            .finalize()
+            .into()
+    }
+
+    fn contains(&self, username: &str, password: &str) -> bool {
+        let key = self.key(username, password);
+
+        self.entries
+            .lock()
+            .is_ok_and(|entries| entries.get(&key).is_some_and(|verified| verified.elapsed() < VERIFIED_CREDENTIALS_TTL))
+    }
+
+    fn insert(&self, username: &str, password: &str) {
+        let key = self.key(username, password);
+
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.retain(|_, verified| verified.elapsed() < VERIFIED_CREDENTIALS_TTL);
+            entries.insert(key, Instant::now());
+        }
+    }
+}
+
+/// Authenticates requests with a header set by a trusted proxy, a session cookie from the login page (or single sign-on),
+/// a bearer api token or Basic credentials.
 #[derive(Clone)]
 pub struct BasicAuth {
     authenticator: Arc<Box<dyn Authenticator>>,
+    throttle: Arc<LoginThrottle>,
+    verified: Arc<VerifiedCredentials>,
+    sessions: Arc<SessionManager>,
+    tokens: Arc<ApiTokens>,
+    oidc: Option<Arc<OidcClient>>,
+    proxy: Option<ProxyConfiguration>,
+    admins: Arc<Vec<String>>,
 }
 
 impl BasicAuth {
-    pub fn new(auth_strategy: &AuthStrategy) -> Result<Self, AppError> {
+    pub fn new(configs: &ServerConfiguration, tokens: Arc<ApiTokens>) -> Result<Self, AppError> {
         Ok(BasicAuth {
-            authenticator: get_authenticator(auth_strategy)?
+            authenticator: get_authenticator(configs.auth_strategy())?,
+            throttle: Arc::new(LoginThrottle::new(configs.throttle().clone())),
+            verified: Arc::new(VerifiedCredentials::new()),
+            sessions: Arc::new(SessionManager::new(configs.session())),
+            tokens,
+            oidc: match configs.auth_strategy() {
+                AuthStrategy::Oidc(oidc) => Some(Arc::new(OidcClient::new(oidc.clone()))),
+                _ => None,
+            },
+            proxy: match configs.auth_strategy() {
+                AuthStrategy::Proxy(proxy) => Some(proxy.clone()),
+                _ => None,
+            },
+            admins: Arc::new(configs.admins().to_vec()),
         })
     }
+
+    /// `name` with their role: admin when listed in `admins`, or when `granted` it by the identity provider.
+    fn user(&self, name: String, granted: Role) -> AuthenticatedUser {
+        let role = match granted {
+            Role::User if !self.admins.contains(&name) => Role::User,
+            _ => Role::Admin,
+        };
+
+        AuthenticatedUser::new(name, role)
+    }
+
+    /// User of an api token, throttled like passwords.
+    fn authenticate_token(&self, address: Option<IpAddr>, value: &str) -> Result<Option<String>, Duration> {
+        let id = format!("token:{}", token_id(value).unwrap_or_default());
+
+        if let Some(retry_after) = self.throttle.retry_after(address, &id) {
+            return Err(retry_after);
+        }
+
+        match self.tokens.authenticate(value) {
+            Ok(Some(user)) => Ok(Some(user)),
+            Ok(None) => {
+                self.throttle.failure(address, &id);
+
+                Ok(None)
+            }
+            Err(e) => {
+                error!("Error checking an api token: {:?}", e);
+
+                Ok(None)
+            }
+        }
+    }
+
+    /// Checks credentials unless the client is locked out, answering how long it must wait in that case.
+    ///
+    /// Checks can bind to a directory or verify a slow hash: call it from `web::block`.
+    pub fn authenticate(&self, address: Option<IpAddr>, username: &str, password: &str) -> Result<bool, Duration> {
+        if let Some(retry_after) = self.throttle.retry_after(address, username) {
+            return Err(retry_after);
+        }
+
+        let valid = self.verified.contains(username, password) || {
+            let valid = self.authenticator.authenticate(username, password);
+
+            if valid {
+                self.verified.insert(username, password);
+            }
+
+            valid
+        };
+
+        if valid {
+            self.throttle.success(address, username);
+
+            Ok(true)
+        } else {
+            self.throttle.failure(address, username);
+
+            Ok(false)
+        }
+    }
+
+    pub fn sessions(&self) -> &SessionManager {
+        &self.sessions
+    }
+
+    /// Provider of single sign-on logins, when configured.
+    pub fn oidc(&self) -> Option<Arc<OidcClient>> {
+        self.oidc.clone()
+    }
 }
 
 impl<S, B> Transform<S> for BasicAuth
     where
-        S: Service<Request=ServiceRequest, Response=ServiceResponse<B>, Error=Error>,
+        S: Service<Request=ServiceRequest, Response=ServiceResponse<B>, Error=Error> + 'static,
         S::Future: 'static,
+        B: 'static,
 {
     type Request = ServiceRequest;
     type Response = ServiceResponse<B>;
@@ -37,68 +219,204 @@ impl<S, B> Transform<S> for BasicAuth
     type Future = Ready<Result<Self::Transform, Self::InitError>>;
 
     fn new_transform(&self, service: S) -> Self::Future {
-        ok(BasicAuthMiddleware { service, authenticator: self.authenticator.clone() })
+        ok(BasicAuthMiddleware { service: Rc::new(RefCell::new(service)), auth: self.clone() })
     }
 }
 
 pub struct BasicAuthMiddleware<S> {
-    service: S,
-    authenticator: Arc<Box<dyn Authenticator>>,
+    service: Rc<RefCell<S>>,
+    auth: BasicAuth,
+}
+
+/// Outcome of checking the credentials of a request.
+enum Identity {
+    User(AuthenticatedUser),
+    Anonymous,
+    Rejected(HttpResponse),
+}
+
+/// Identifies the user of a request; passwords and tokens are checked on the blocking thread pool.
+async fn identify(auth: &BasicAuth, req: &ServiceRequest) -> Result<Identity, AppError> {
+    if let Some(user) = auth.proxy.as_ref().and_then(|proxy| proxy_user(proxy, req)) {
+        return Ok(Identity::User(auth.user(user, Role::User)));
+    }
+
+    if let Some(user) = req.cookie(SESSION_COOKIE).and_then(|cookie| auth.sessions().user(cookie.value())) {
+        return Ok(Identity::User(auth.user(user.name, user.role)));
+    }
+
+    let address = req.peer_addr().map(|addr| addr.ip());
+
+    let authorization = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok());
+
+    if let Some(token) = authorization.and_then(|value| value.strip_prefix("Bearer ")) {
+        let (checker, token) = (auth.clone(), token.to_string());
+
+        return Ok(match web::block(move || Ok::<_, AppError>(checker.authenticate_token(address, &token))).await? {
+            Ok(Some(user)) => Identity::User(auth.user(user, Role::User)),
+            Ok(None) => Identity::Rejected(HttpResponse::Unauthorized().finish()),
+            Err(retry_after) => Identity::Rejected(too_many_logins(retry_after)),
+        });
+    }
+
+    let credentials = basic_credentials(req)
+        .and_then(|credentials| credentials.split_once(':').map(|(user, password)| (user.to_string(), password.to_string())));
+
+    if let Some((user, password)) = credentials {
+        let checker = auth.clone();
+
+        let checked = web::block(move || {
+            Ok::<_, AppError>(checker.authenticate(address, &user, &password).map(|valid| valid.then_some(user)))
+        }).await?;
+
+        return Ok(match checked {
+            Ok(Some(user)) => Identity::User(auth.user(user, Role::User)),
+            Ok(None) => Identity::Anonymous,
+            Err(retry_after) => Identity::Rejected(too_many_logins(retry_after)),
+        });
+    }
+
+    Ok(Identity::Anonymous)
+}
+
+/// Decoded `user:password` of a Basic authorization header.
+fn basic_credentials(req: &ServiceRequest) -> Option<String> {
+    req.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|value| value.strip_prefix("Basic "))
+        .and_then(|v| base64::engine::general_purpose::STANDARD.decode(v).ok())
+        .map(|values| String::from_utf8(values).unwrap_or_default())
 }
 
-impl<S> BasicAuthMiddleware<S> {
-    fn authenticate(&self, username: &str, password: &str) -> bool {
-        self.authenticator.authenticate(username, password)
+/// Answer to a request without valid credentials.
+fn challenge(auth: &BasicAuth, req: &ServiceRequest) -> HttpResponse {
+    // Behind a proxy there is no login page nor password to ask for.
+    if auth.proxy.is_some() {
+        return HttpResponse::Unauthorized().finish();
+    }
+
+    let is_page = req.method() == Method::GET && req
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|h| h.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/html"));
+
+    // Browsers go to the login page, scripts keep getting a Basic challenge.
+    if is_page && basic_credentials(req).is_none() {
+        let next = req.uri().path_and_query().map(|path| path.as_str()).unwrap_or("/");
+        let location = format!("/login?next={}", percent_encode(next));
+
+        return HttpResponse::SeeOther()
+            .set_header(header::LOCATION, location)
+            .finish();
     }
+
+    HttpResponse::Unauthorized()
+        .set_header(
+            "WWW-Authenticate",
+            "Basic realm=\"User Visible Realm\", charset=\"UTF-8\"",
+        )
+        .finish()
 }
 
 impl<S, B> Service for BasicAuthMiddleware<S>
     where
-        S: Service<Request=ServiceRequest, Response=ServiceResponse<B>, Error=Error>,
+        S: Service<Request=ServiceRequest, Response=ServiceResponse<B>, Error=Error> + 'static,
         S::Future: 'static,
+        B: 'static,
 {
     type Request = ServiceRequest;
     type Response = ServiceResponse<B>;
     type Error = Error;
-    type Future = Either<S::Future, Ready<Result<Self::Response, Self::Error>>>;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
 
     fn poll_ready(&mut self, ctx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        self.service.poll_ready(ctx)
+        self.service.borrow_mut().poll_ready(ctx)
     }
 
     fn call(&mut self, req: Self::Request) -> Self::Future {
-        let is_auth = req
-            .headers()
-            .get(actix_web::http::header::AUTHORIZATION)
-            .map(|h| h.to_str().ok())
-            .flatten()
-            .map(|value| {
-                value
-                    .strip_prefix("Basic ")
-                    .map(|v| {
-                        base64::engine::general_purpose::STANDARD.decode(v)
-                            .map(|values| String::from_utf8(values).unwrap_or(String::new()))
-                            .map(|credentials| match credentials.split_once(":") {
-                                Some((user, password)) => self.authenticate(user, password),
-                                _ => false
-                            })
-                            .unwrap_or(false)
-                    })
-            })
-            .flatten();
-
-        if let Some(true) = is_auth {
-            Either::Left(self.service.call(req))
-        } else {
-            Either::Right(ok(req.into_response(
-                HttpResponse::Unauthorized()
-                    .set_header(
-                        "WWW-Authenticate",
-                        "Basic realm=\"User Visible Realm\", charset=\"UTF-8\"",
-                    )
-                    .finish()
-                    .into_body(),
-            )))
+        let service = self.service.clone();
+        let auth = self.auth.clone();
+
+        Box::pin(async move {
+            let response = match identify(&auth, &req).await? {
+                Identity::User(user) => {
+                    req.extensions_mut().insert(user);
+
+                    let response = service.borrow_mut().call(req);
+
+                    return response.await;
+                }
+                Identity::Rejected(response) => response,
+                Identity::Anonymous => challenge(&auth, &req),
+            };
+
+            Ok(req.into_response(response.into_body()))
+        })
+    }
+}
+
+/// User named by the proxy header, only believed when the request comes from one of the trusted proxies.
+fn proxy_user(proxy: &ProxyConfiguration, req: &ServiceRequest) -> Option<String> {
+    let user = req
+        .headers()
+        .get(proxy.header())
+        .and_then(|h| h.to_str().ok())
+        .map(|user| user.trim())
+        .filter(|user| !user.is_empty())?;
+
+    match req.peer_addr().map(|addr| addr.ip()) {
+        Some(address) if proxy.is_trusted(&address) => Some(user.to_string()),
+        address => {
+            warn!("Ignoring {} header from {:?}, which is not a trusted proxy", proxy.header(), address);
+
+            None
         }
     }
-}
\ No newline at end of file
+}
+
+/// Answer to a client locked out after too many failed logins.
+fn too_many_logins(retry_after: Duration) -> HttpResponse {
+    HttpResponse::TooManyRequests()
+        .set_header(header::RETRY_AFTER, retry_after.as_secs_f64().ceil().to_string())
+        .set_header(header::CONTENT_TYPE, "text/plain")
+        .body("Too many failed logins, try again later")
+}
+
+#[test]
+fn proxy_user_works() {
+    use actix_web::test::TestRequest;
+
+    let configs = ini::Ini::load_from_str("[auth_proxy]\nheader=X-Remote-User\ntrusted_proxies=10.0.0.0/8, ::1").unwrap();
+    let proxy = ProxyConfiguration::try_from(&configs).unwrap();
+
+    let request = |peer: &str, user: &str| TestRequest::default()
+        .peer_addr(peer.parse().unwrap())
+        .header("X-Remote-User", user)
+        .to_srv_request();
+
+    assert_eq!(proxy_user(&proxy, &request("10.1.2.3:5000", "alice")), Some("alice".to_string()));
+    assert_eq!(proxy_user(&proxy, &request("[::1]:5000", "alice")), Some("alice".to_string()));
+    assert_eq!(proxy_user(&proxy, &request("[::ffff:10.0.0.1]:5000", "alice")), Some("alice".to_string()));
+    assert_eq!(proxy_user(&proxy, &request("192.168.1.10:5000", "alice")), None);
+    assert_eq!(proxy_user(&proxy, &request("10.1.2.3:5000", " ")), None);
+    assert_eq!(proxy_user(&proxy, &TestRequest::default().peer_addr("10.1.2.3:5000".parse().unwrap()).to_srv_request()), None);
+
+    assert!(ProxyConfiguration::try_from(&ini::Ini::load_from_str("[auth_proxy]\nheader=X-Remote-User").unwrap()).is_err());
+}
+
+#[test]
+fn verified_credentials_work() {
+    let verified = VerifiedCredentials::new();
+
+    verified.insert("alice", "secret");
+
+    assert!(verified.contains("alice", "secret"));
+    assert!(!verified.contains("alice", "wrong"));
+    assert!(!verified.contains("alic", "esecret"));
+    assert!(!verified.contains("bob", "secret"));
+}
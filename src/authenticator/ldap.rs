@@ -0,0 +1,169 @@
+use ldap3::{dn_escape, ldap_escape, LdapConn, LdapConnSettings, LdapError, Scope, SearchEntry};
+
+use crate::app_configs::{LdapConfiguration, LdapUserLookup};
+use crate::authenticator::Authenticator;
+
+/// Result code of a bind with a wrong password.
+const INVALID_CREDENTIALS: u32 = 49;
+
+/// Checks passwords by binding to a directory server as the user.
+pub struct LdapAuthenticator {
+    configs: LdapConfiguration,
+}
+
+impl LdapAuthenticator {
+    pub fn new(configs: LdapConfiguration) -> Self {
+        LdapAuthenticator { configs }
+    }
+
+    fn connect(&self) -> Result<LdapConn, LdapError> {
+        let settings = LdapConnSettings::new()
+            .set_conn_timeout(self.configs.timeout())
+            .set_starttls(self.configs.starttls())
+            .set_no_tls_verify(!self.configs.tls_verify());
+
+        LdapConn::with_settings(settings, self.configs.url())
+    }
+
+    /// Binds as the service account, if any, to search the directory.
+    fn service_bind(&self, conn: &mut LdapConn) -> Result<(), LdapError> {
+        if let Some(bind_dn) = self.configs.bind_dn() {
+            conn.with_timeout(self.configs.timeout())
+                .simple_bind(bind_dn, self.configs.bind_password().unwrap_or_default())?
+                .success()?;
+        }
+
+        Ok(())
+    }
+
+    /// Dn of `username`, `None` when the directory has no single entry for it.
+    fn user_dn(&self, conn: &mut LdapConn, username: &str) -> Result<Option<String>, LdapError> {
+        match self.configs.lookup() {
+            LdapUserLookup::Template(template) => Ok(Some(template.replace("{username}", &dn_escape(username)))),
+            LdapUserLookup::Search { base_dn, user_filter } => {
+                self.service_bind(conn)?;
+
+                let filter = user_filter.replace("{username}", &ldap_escape(username));
+
+                let (mut entries, _) = conn
+                    .with_timeout(self.configs.timeout())
+                    .search(base_dn, Scope::Subtree, &filter, vec!["1.1"])?
+                    .success()?;
+
+                if entries.len() != 1 {
+                    return Ok(None);
+                }
+
+                Ok(entries.pop().map(|entry| SearchEntry::construct(entry).dn))
+            }
+        }
+    }
+
+    fn is_member(&self, conn: &mut LdapConn, group: &str, dn: &str, username: &str) -> Result<bool, LdapError> {
+        let filter = self.configs.group_filter()
+            .replace("{dn}", &ldap_escape(dn))
+            .replace("{username}", &ldap_escape(username));
+
+        let (entries, _) = conn
+            .with_timeout(self.configs.timeout())
+            .search(group, Scope::Base, &filter, vec!["1.1"])?
+            .success()?;
+
+        Ok(!entries.is_empty())
+    }
+
+    fn check(&self, username: &str, password: &str) -> Result<bool, LdapError> {
+        let mut conn = self.connect()?;
+
+        let dn = match self.user_dn(&mut conn, username)? {
+            Some(dn) => dn,
+            None => return Ok(false),
+        };
+
+        let bind = conn.with_timeout(self.configs.timeout()).simple_bind(&dn, password)?;
+
+        if bind.rc == INVALID_CREDENTIALS {
+            return Ok(false);
+        }
+
+        bind.success()?;
+
+        if let Some(group) = self.configs.required_group() {
+            self.service_bind(&mut conn)?;
+
+            if !self.is_member(&mut conn, group, &dn, username)? {
+                info!("User {} is not a member of {}", username, group);
+
+                return Ok(false);
+            }
+        }
+
+        let _ = conn.unbind();
+
+        Ok(true)
+    }
+}
+
+impl Authenticator for LdapAuthenticator {
+    fn authenticate(&self, username: &str, password: &str) -> bool {
+        // Servers accept binds without a password as anonymous ones.
+        if username.is_empty() || password.is_empty() {
+            return false;
+        }
+
+        match self.check(username, password) {
+            Ok(valid) => valid,
+            Err(e) => {
+                error!("Error authenticating {} with ldap: {}", username, e);
+
+                false
+            }
+        }
+    }
+}
+
+/// Needs a local server: `docker run -p 389:389 osixia/openldap`, then `cargo test -- --ignored`.
+#[test]
+#[ignore]
+fn ldap_authenticator_works() {
+    let authenticator = |section: &str| {
+        let configs = ini::Ini::load_from_str(&format!("[auth_ldap]\nurl=ldap://127.0.0.1:389\n{}", section)).unwrap();
+
+        LdapAuthenticator::new(LdapConfiguration::try_from(&configs).unwrap())
+    };
+
+    let bind = authenticator("user_dn=cn={username},dc=example,dc=org");
+
+    assert!(bind.authenticate("admin", "admin"));
+    assert!(!bind.authenticate("admin", "wrong"));
+    assert!(!bind.authenticate("admin", ""));
+    assert!(!bind.authenticate("nobody", "admin"));
+
+    let search = authenticator("base_dn=dc=example,dc=org\nuser_filter=(cn={username})\nbind_dn=cn=admin,dc=example,dc=org\nbind_password=admin");
+
+    assert!(search.authenticate("admin", "admin"));
+    assert!(!search.authenticate("admin", "wrong"));
+    assert!(!search.authenticate("*", "admin"));
+
+    let group = authenticator("user_dn=cn={username},dc=example,dc=org\nrequired_group=cn=fshare,dc=example,dc=org");
+
+    assert!(!group.authenticate("admin", "admin"));
+}
+
+#[test]
+fn ldap_configuration_works() {
+    let parse = |section: &str| LdapConfiguration::try_from(&ini::Ini::load_from_str(&format!("[auth_ldap]\n{}", section)).unwrap());
+
+    let configs = parse("url=ldaps://ldap.example.org\nbase_dn=ou=people,dc=example,dc=org").unwrap();
+
+    assert_eq!(configs.lookup(), &LdapUserLookup::Search {
+        base_dn: "ou=people,dc=example,dc=org".to_string(),
+        user_filter: "(uid={username})".to_string(),
+    });
+
+    assert!(parse("url=ldap://ldap.example.org\nuser_dn=uid={username},dc=example,dc=org\nstarttls=true").is_ok());
+    assert!(parse("url=ldaps://ldap.example.org\nuser_dn=uid={username},dc=example,dc=org\nstarttls=true").is_err());
+    assert!(parse("url=ldap://ldap.example.org\nuser_dn=uid=admin,dc=example,dc=org").is_err());
+    assert!(parse("url=http://ldap.example.org\nbase_dn=dc=example,dc=org").is_err());
+    assert!(parse("base_dn=dc=example,dc=org").is_err());
+}
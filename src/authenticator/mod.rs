@@ -1,11 +1,13 @@
 use std::sync::Arc;
 
 use crate::app_configs::AuthStrategy;
+use crate::authenticator::ldap::LdapAuthenticator;
 #[cfg(target_os = "linux")]
 use crate::authenticator::pam::PamAuthenticator;
-use crate::authenticator::passwd::PasswdFileAuthenticator;
+pub use crate::authenticator::passwd::PasswdFileAuthenticator;
 use crate::errors::AppError;
 
+mod ldap;
 #[cfg(target_os = "linux")]
 mod pam;
 mod passwd;
@@ -14,15 +16,28 @@ pub trait Authenticator: Sync + Send {
     fn authenticate(&self, username: &str, password: &str) -> bool;
 }
 
+/// Accepts no password, for users logging in with single sign-on, through a proxy or with api tokens.
+struct NoPasswords;
+
+impl Authenticator for NoPasswords {
+    fn authenticate(&self, _username: &str, _password: &str) -> bool {
+        false
+    }
+}
+
 
 pub fn get_authenticator(auth_strategy: &AuthStrategy) -> Result<Arc<Box<dyn Authenticator>>, AppError> {
     Ok(match auth_strategy {
-        AuthStrategy::File(file) => {
-            Arc::new(Box::new(PasswdFileAuthenticator::new(file)?))
+        AuthStrategy::File { users_file, allow_plaintext } => {
+            Arc::new(Box::new(PasswdFileAuthenticator::new(users_file, *allow_plaintext)?))
         }
         #[cfg(target_os = "linux")]
         AuthStrategy::PamModule(pam_module) => {
             Arc::new(Box::new(PamAuthenticator::new(pam_module.to_string())))
         }
+        AuthStrategy::Ldap(configs) => {
+            Arc::new(Box::new(LdapAuthenticator::new(configs.clone())))
+        }
+        AuthStrategy::Oidc(_) | AuthStrategy::Proxy(_) => Arc::new(Box::new(NoPasswords)),
     })
 }
@@ -1,47 +1,104 @@
 use std::collections::HashMap;
+use std::path::Path;
 
 use crate::authenticator::Authenticator;
 use crate::errors::AppError;
+use crate::password::{hash_password, is_hash, plaintext_eq, verify_password};
 
 pub struct PasswdFileAuthenticator {
     users: HashMap<String, String>,
+    allow_plaintext: bool,
+}
+
+/// Splits a `user password` or htpasswd style `user:hash` line.
+fn parse_line(line: &str) -> Option<(&str, &str)> {
+    if line.starts_with('#') {
+        return None;
+    }
+
+    line.find([' ', ':'])
+        .map(|index| (&line[..index], line[index + 1..].trim()))
+        .filter(|(user, pass)| !user.is_empty() && !pass.is_empty())
 }
 
 impl PasswdFileAuthenticator {
-    pub fn new(file: &std::path::PathBuf) -> Result<Self, AppError> {
+    pub fn new(file: &std::path::PathBuf, allow_plaintext: bool) -> Result<Self, AppError> {
         let mut users = HashMap::new();
 
         let file_content = std::fs::read_to_string(file)
             .map_err(|e| AppError::InitError(format!("Failed to read password file:{}", e)))?;
 
-        let lines = file_content.lines();
-
-        lines
-            .filter(|l| !l.starts_with("#"))
-            .for_each(|line| {
-                let some_parts = line.split_once(" ");
-
-                if let Some((user, pass)) = some_parts {
-                    users.insert(user.to_string(), pass.to_string());
+        file_content
+            .lines()
+            .filter_map(parse_line)
+            .for_each(|(user, pass)| {
+                if !allow_plaintext && !is_hash(pass) {
+                    warn!("Ignoring user {} with a plaintext password, set allow_plaintext=true to accept it", user);
+                    return;
                 }
+
+                users.insert(user.to_string(), pass.to_string());
             });
 
         Ok(PasswdFileAuthenticator {
-            users
+            users,
+            allow_plaintext,
         })
     }
+
+    /// Adds `username` to the password file, or replaces its password, storing an argon2 hash.
+    pub fn set_password(file: &Path, username: &str, password: &str) -> Result<(), AppError> {
+        if username.is_empty() || username.starts_with('#') || username.contains([' ', ':']) {
+            return Err(AppError::InvalidRequest(format!("Invalid username: {}", username)));
+        }
+
+        let entry = format!("{} {}", username, hash_password(password)?);
+
+        let content = if file.exists() { std::fs::read_to_string(file)? } else { String::new() };
+
+        let mut replaced = false;
+
+        let mut lines: Vec<String> = content
+            .lines()
+            .map(|line| match parse_line(line) {
+                Some((user, _)) if user == username => {
+                    replaced = true;
+                    entry.clone()
+                }
+                _ => line.to_string(),
+            })
+            .collect();
+
+        if !replaced {
+            lines.push(entry);
+        }
+
+        std::fs::write(file, lines.join("\n") + "\n")?;
+
+        Ok(())
+    }
 }
 
 impl Clone for PasswdFileAuthenticator {
     fn clone(&self) -> Self {
         PasswdFileAuthenticator {
-            users: self.users.clone()
+            users: self.users.clone(),
+            allow_plaintext: self.allow_plaintext,
         }
     }
 }
 
 impl Authenticator for PasswdFileAuthenticator {
     fn authenticate(&self, username: &str, password: &str) -> bool {
-        self.users.get(username).map(|pass| pass.eq(password)).unwrap_or(false)
+        self.users
+            .get(username)
+            .map(|pass| {
+                if is_hash(pass) {
+                    verify_password(password, pass)
+                } else {
+                    self.allow_plaintext && plaintext_eq(password, pass)
+                }
+            })
+            .unwrap_or(false)
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,137 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use sha2::{Digest, Sha256};
+
+use crate::errors::AppError;
+use crate::index::{UploadIndex, UploadRecord};
+use crate::metadata::{BlobFile, UploadMetadata};
+use crate::storage::StorageBackend;
+
+/// Files of deduplicated uploads, stored once by content as `blobs/<sha256>` and reference counted in the index.
+#[derive(Debug)]
+pub struct BlobStore {
+    storage: Arc<dyn StorageBackend>,
+    index: Arc<UploadIndex>,
+    /// Held while blobs are stored or deleted, so a blob is never deleted as a new upload starts using it.
+    lock: Mutex<()>,
+}
+
+impl BlobStore {
+    pub fn new(storage: Arc<dyn StorageBackend>, index: Arc<UploadIndex>) -> Self {
+        BlobStore { storage, index, lock: Mutex::new(()) }
+    }
+
+    pub fn key(sha256: &str) -> String {
+        format!("blobs/{}", sha256)
+    }
+
+    /// Stores the blobs of an upload that are not stored yet, from `<staging>/<sha256>`, then indexes the upload.
+    pub fn register(&self, staging: &Path, record: &UploadRecord) -> Result<(), AppError> {
+        let _guard = self.lock()?;
+
+        for blob in record.metadata.blobs.iter().flatten() {
+            let staged = staging.join(&blob.sha256);
+
+            // The same content may be sent twice in one upload, its staged file is gone after the first.
+            if !staged.exists() {
+                continue;
+            }
+
+            if self.storage.stat(&Self::key(&blob.sha256))?.is_some() {
+                debug!("Reusing blob {} for {}", blob.sha256, blob.name);
+
+                std::fs::remove_file(staged)?;
+            } else {
+                self.storage.put_file(&Self::key(&blob.sha256), &staged)?;
+            }
+        }
+
+        record.metadata.write(self.storage.as_ref(), &record.link)?;
+
+        self.index.insert(record)
+    }
+
+    /// Deletes the blobs that no upload references anymore.
+    pub fn release(&self, blobs: &[String]) -> Result<(), AppError> {
+        let _guard = self.lock()?;
+
+        for sha256 in blobs {
+            if self.index.blob_refs(sha256)? == 0 {
+                self.storage.delete(&Self::key(sha256))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, ()>, AppError> {
+        self.lock
+            .lock()
+            .map_err(|_| AppError::ThreadError("blob store lock poisoned".to_string()))
+    }
+}
+
+/// Index record of a deduplicated upload, from the files listed in its metadata.
+pub fn blob_record(link: &str, metadata: UploadMetadata) -> UploadRecord {
+    let blobs = metadata.blobs.clone().unwrap_or_default();
+
+    UploadRecord {
+        link: link.to_string(),
+        size: blobs.iter().map(|blob| blob.size).sum(),
+        files: blobs
+            .into_iter()
+            .map(|blob| crate::index::IndexedFile { name: blob.name, size: blob.size, sha256: Some(blob.sha256) })
+            .collect(),
+        metadata,
+    }
+}
+
+/// File being staged, hashed as it is written.
+pub struct StagedBlob {
+    file: std::fs::File,
+    path: PathBuf,
+    sha256: Sha256,
+    crc32: crc32fast::Hasher,
+    size: u64,
+}
+
+impl StagedBlob {
+    pub fn create(path: PathBuf) -> Result<Self, AppError> {
+        Ok(StagedBlob {
+            file: std::fs::File::create(&path)?,
+            path,
+            sha256: Sha256::new(),
+            crc32: crc32fast::Hasher::new(),
+            size: 0,
+        })
+    }
+
+    /// Renames the staged file after its hash, next to where it was written.
+    pub fn finish(mut self, name: String) -> Result<BlobFile, AppError> {
+        self.file.flush()?;
+
+        let sha256 = format!("{:x}", self.sha256.finalize());
+
+        std::fs::rename(&self.path, self.path.with_file_name(&sha256))?;
+
+        Ok(BlobFile { name, size: self.size, sha256, crc32: self.crc32.finalize() })
+    }
+}
+
+impl Write for StagedBlob {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.file.write(buf)?;
+
+        self.sha256.update(&buf[..written]);
+        self.crc32.update(&buf[..written]);
+        self.size += written as u64;
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
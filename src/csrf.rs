@@ -0,0 +1,39 @@
+use actix_web::{HttpMessage, HttpRequest};
+use actix_web::cookie::{Cookie, SameSite};
+
+use crate::errors::AppError;
+use crate::password::plaintext_eq;
+use crate::utils::generate_token;
+
+/// Cookie holding the csrf token; forms must send the same value, which other sites cannot read.
+pub const CSRF_COOKIE: &str = "fshare_csrf";
+
+/// Token to embed in the forms of a page, with the cookie to set when the browser has none yet.
+pub fn token(req: &HttpRequest) -> (String, Option<Cookie<'static>>) {
+    if let Some(cookie) = req.cookie(CSRF_COOKIE).filter(|cookie| !cookie.value().is_empty()) {
+        return (cookie.value().to_string(), None);
+    }
+
+    let token = generate_token();
+
+    let cookie = Cookie::build(CSRF_COOKIE, token.clone())
+        .path("/")
+        .http_only(true)
+        .same_site(SameSite::Strict)
+        .secure(req.connection_info().scheme() == "https")
+        .finish();
+
+    (token, Some(cookie))
+}
+
+/// Checks the token sent by a form against the cookie.
+pub fn verify(req: &HttpRequest, token: &str) -> Result<(), AppError> {
+    match req.cookie(CSRF_COOKIE) {
+        Some(cookie) if !token.is_empty() && plaintext_eq(token, cookie.value()) => Ok(()),
+        _ => {
+            warn!("Rejected a form post without a valid csrf token: {}", req.path());
+
+            Err(AppError::Forbidden("Invalid csrf token, reload the page and try again".to_string()))
+        }
+    }
+}
@@ -12,6 +12,17 @@ pub enum AppError {
     ArchiveError(String),
     UploadFailed(String),
     ThreadError(String),
+    InvalidRequest(String),
+    Conflict(String),
+    PreconditionFailed(String),
+    UnsupportedMediaType(String),
+    PayloadTooLarge(String),
+    QuotaExceeded(String),
+    Gone,
+    DatabaseError(String),
+    PasswordRequired,
+    InvalidPassword,
+    Forbidden(String),
     FileNotFound
 }
 
@@ -23,6 +34,17 @@ impl std::fmt::Display for AppError {
             AppError::ArchiveError(ref reason) => write!(f, "zip error {}", reason),
             AppError::UploadFailed(ref reason) => write!(f, "upload error {}", reason),
             AppError::ThreadError(ref reason) => write!(f, "thread error {}", reason),
+            AppError::InvalidRequest(ref reason) => write!(f, "invalid request {}", reason),
+            AppError::Conflict(ref reason) => write!(f, "conflict {}", reason),
+            AppError::PreconditionFailed(ref reason) => write!(f, "precondition failed {}", reason),
+            AppError::UnsupportedMediaType(ref reason) => write!(f, "unsupported media type {}", reason),
+            AppError::PayloadTooLarge(ref reason) => write!(f, "payload too large {}", reason),
+            AppError::QuotaExceeded(ref reason) => write!(f, "quota exceeded {}", reason),
+            AppError::Gone => write!(f, "link expired"),
+            AppError::DatabaseError(ref reason) => write!(f, "database error {}", reason),
+            AppError::PasswordRequired => write!(f, "password required"),
+            AppError::InvalidPassword => write!(f, "invalid password"),
+            AppError::Forbidden(ref reason) => write!(f, "forbidden {}", reason),
             AppError::FileNotFound => {write!(f, "file not found")}
         }
     }
@@ -44,7 +66,7 @@ impl<E: std::fmt::Debug + Into<AppError>> From<actix_web::error::BlockingError<E
     fn from(e: BlockingError<E>) -> Self {
         match e {
             BlockingError::Error(e) => e.into(),
-            BlockingError::Canceled => AppError::ThreadError(format!("Thread canceled")),
+            BlockingError::Canceled => AppError::ThreadError("Thread canceled".to_string()),
         }
     }
 }
@@ -63,6 +85,30 @@ impl actix_web::error::ResponseError for AppError {
             AppError::FileSystemError(ref reason) => HttpResponse::InternalServerError()
                 .set_header("Content-Type", "text/plain")
                 .body(reason),
+            AppError::InvalidRequest(ref reason) => HttpResponse::BadRequest()
+                .set_header("Content-Type", "text/plain")
+                .body(reason),
+            AppError::Conflict(ref reason) => HttpResponse::Conflict()
+                .set_header("Content-Type", "text/plain")
+                .body(reason),
+            AppError::PreconditionFailed(ref reason) => HttpResponse::PreconditionFailed()
+                .set_header("Content-Type", "text/plain")
+                .body(reason),
+            AppError::UnsupportedMediaType(ref reason) => HttpResponse::UnsupportedMediaType()
+                .set_header("Content-Type", "text/plain")
+                .body(reason),
+            AppError::PayloadTooLarge(ref reason) => HttpResponse::PayloadTooLarge()
+                .set_header("Content-Type", "text/plain")
+                .body(reason),
+            AppError::QuotaExceeded(ref reason) => HttpResponse::PayloadTooLarge()
+                .set_header("Content-Type", "text/plain")
+                .body(reason),
+            AppError::Gone => HttpResponse::Gone().finish(),
+            AppError::PasswordRequired => HttpResponse::Unauthorized().finish(),
+            AppError::InvalidPassword => HttpResponse::Forbidden().finish(),
+            AppError::Forbidden(ref reason) => HttpResponse::Forbidden()
+                .set_header("Content-Type", "text/plain")
+                .body(reason),
             AppError::FileNotFound => HttpResponse::NotFound().finish(),
             _ => HttpResponse::InternalServerError().finish(),
         }
@@ -81,6 +127,12 @@ impl From<std::time::SystemTimeError> for AppError {
     }
 }
 
+impl From<rusqlite::Error> for AppError {
+    fn from(err: rusqlite::Error) -> Self {
+        AppError::DatabaseError(err.to_string())
+    }
+}
+
 impl From<tera::Error> for AppError {
     fn from(err: tera::Error) -> Self {
         AppError::InitError(err.to_string())
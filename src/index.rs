@@ -0,0 +1,694 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+
+use rusqlite::{Connection, OptionalExtension, Transaction, params};
+use serde::Serialize;
+
+use crate::errors::AppError;
+use crate::metadata::UploadMetadata;
+
+/// Schema changes, applied in order and tracked with sqlite's `user_version`.
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE uploads (
+        link TEXT PRIMARY KEY,
+        created INTEGER NOT NULL,
+        expires_at INTEGER,
+        size INTEGER NOT NULL,
+        metadata TEXT NOT NULL
+    );
+    CREATE INDEX uploads_expires_at ON uploads (expires_at);
+    CREATE TABLE files (
+        link TEXT NOT NULL,
+        name TEXT NOT NULL,
+        size INTEGER NOT NULL
+    );
+    CREATE INDEX files_link ON files (link);",
+    "ALTER TABLE uploads ADD COLUMN downloads INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE uploads ADD COLUMN max_downloads INTEGER;",
+    "ALTER TABLE uploads ADD COLUMN uploader TEXT;
+    CREATE INDEX uploads_uploader ON uploads (uploader);",
+    "ALTER TABLE files ADD COLUMN sha256 TEXT;
+    CREATE TABLE blobs (
+        sha256 TEXT PRIMARY KEY,
+        size INTEGER NOT NULL,
+        refs INTEGER NOT NULL
+    );",
+];
+
+/// Schema changes of the state database, attached as `state`. It keeps what cannot be recreated
+/// from the storage, so that deleting or rebuilding the index never loses it.
+const STATE_MIGRATIONS: &[&str] = &[
+    "CREATE TABLE state.upload_requests (
+        token TEXT PRIMARY KEY,
+        creator TEXT NOT NULL,
+        created INTEGER NOT NULL,
+        expires_at INTEGER,
+        max_size INTEGER,
+        max_files INTEGER,
+        label TEXT,
+        uploads INTEGER NOT NULL DEFAULT 0
+    );
+    CREATE INDEX state.upload_requests_creator ON upload_requests (creator);",
+    "CREATE TABLE state.api_tokens (
+        id TEXT PRIMARY KEY,
+        user TEXT NOT NULL,
+        name TEXT NOT NULL,
+        hash TEXT NOT NULL,
+        created INTEGER NOT NULL,
+        expires_at INTEGER,
+        last_used INTEGER
+    );
+    CREATE INDEX state.api_tokens_user ON api_tokens (user);",
+    "ALTER TABLE state.upload_requests ADD COLUMN files INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE state.upload_requests ADD COLUMN size INTEGER NOT NULL DEFAULT 0;",
+];
+
+#[derive(Serialize, Clone, Debug)]
+pub struct IndexedFile {
+    pub name: String,
+    pub size: u64,
+    pub sha256: Option<String>,
+}
+
+/// Everything known about an upload without opening its archive.
+#[derive(Clone, Debug)]
+pub struct UploadRecord {
+    pub link: String,
+    pub size: u64,
+    pub metadata: UploadMetadata,
+    pub files: Vec<IndexedFile>,
+}
+
+/// A link letting anyone upload files on behalf of its creator, without an account.
+#[derive(Serialize, Clone, Debug)]
+pub struct UploadRequest {
+    pub token: String,
+    pub creator: String,
+    pub created: u64,
+    pub expires_at: Option<u64>,
+    /// Most bytes accepted, over all the uploads received.
+    pub max_size: Option<u64>,
+    /// Most files accepted, over all the uploads received.
+    pub max_files: Option<u64>,
+    /// Given to the uploads received through the link.
+    pub label: Option<String>,
+    /// Number of uploads received so far.
+    pub uploads: u64,
+    /// Files and bytes received so far.
+    pub files: u64,
+    pub size: u64,
+}
+
+impl UploadRequest {
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+
+    /// Bytes still accepted, `None` when unlimited.
+    pub fn remaining_size(&self) -> Option<u64> {
+        self.max_size.map(|max_size| max_size.saturating_sub(self.size))
+    }
+
+    /// Files still accepted, `None` when unlimited.
+    pub fn remaining_files(&self) -> Option<u64> {
+        self.max_files.map(|max_files| max_files.saturating_sub(self.files))
+    }
+}
+
+/// A personal api token, whose secret is only kept hashed.
+#[derive(Serialize, Clone, Debug)]
+pub struct ApiToken {
+    pub id: String,
+    pub user: String,
+    pub name: String,
+    pub created: u64,
+    pub expires_at: Option<u64>,
+    pub last_used: Option<u64>,
+}
+
+#[derive(Debug)]
+pub struct UploadIndex {
+    connection: Mutex<Connection>,
+}
+
+impl UploadIndex {
+    /// Opens (or creates) the index and the state database next to it, returning whether the index
+    /// was empty and needs to be rebuilt.
+    pub fn open(path: &Path, state_path: &Path) -> Result<(Self, bool), AppError> {
+        let mut connection = Connection::open(path)
+            .map_err(|e| AppError::InitError(format!("Error opening upload index {}: {}", path.display(), e)))?;
+
+        connection
+            .execute("ATTACH DATABASE ?1 AS state", params![state_path.to_string_lossy()])
+            .map_err(|e| AppError::InitError(format!("Error opening state database {}: {}", state_path.display(), e)))?;
+
+        migrate(&mut connection, "state", STATE_MIGRATIONS)?;
+
+        let version = migrate(&mut connection, "main", MIGRATIONS)?;
+
+        Ok((UploadIndex { connection: Mutex::new(connection) }, version == 0))
+    }
+
+    fn connection(&self) -> Result<MutexGuard<'_, Connection>, AppError> {
+        self.connection
+            .lock()
+            .map_err(|_| AppError::ThreadError("upload index lock poisoned".to_string()))
+    }
+
+    pub fn insert(&self, record: &UploadRecord) -> Result<(), AppError> {
+        let metadata = serde_json::to_string(&record.metadata)
+            .map_err(|e| AppError::DatabaseError(e.to_string()))?;
+
+        let mut connection = self.connection()?;
+
+        let transaction = connection.transaction()?;
+
+        release_blobs(&transaction, &record.link)?;
+
+        transaction.execute(
+            "INSERT OR REPLACE INTO uploads (link, created, expires_at, size, metadata, downloads, max_downloads, uploader)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                record.link,
+                record.metadata.created,
+                record.metadata.expires_at,
+                record.size,
+                metadata,
+                record.metadata.downloads,
+                record.metadata.max_downloads,
+                record.metadata.uploader
+            ],
+        )?;
+
+        transaction.execute("DELETE FROM files WHERE link = ?1", params![record.link])?;
+
+        for file in &record.files {
+            transaction.execute(
+                "INSERT INTO files (link, name, size, sha256) VALUES (?1, ?2, ?3, ?4)",
+                params![record.link, file.name, file.size, file.sha256],
+            )?;
+        }
+
+        for blob in record.metadata.blobs.iter().flatten() {
+            transaction.execute(
+                "INSERT INTO blobs (sha256, size, refs) VALUES (?1, ?2, 1)
+                 ON CONFLICT (sha256) DO UPDATE SET refs = refs + 1",
+                params![blob.sha256, blob.size],
+            )?;
+        }
+
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    pub fn get(&self, link: &str) -> Result<Option<UploadRecord>, AppError> {
+        Ok(self.query(Some(link), None)?.pop())
+    }
+
+    /// Every upload, or only those of `uploader`, newest first.
+    pub fn list(&self, uploader: Option<&str>) -> Result<Vec<UploadRecord>, AppError> {
+        self.query(None, uploader)
+    }
+
+    fn query(&self, link: Option<&str>, uploader: Option<&str>) -> Result<Vec<UploadRecord>, AppError> {
+        let connection = self.connection()?;
+
+        let mut uploads = connection.prepare(
+            "SELECT link, size, metadata, downloads FROM uploads
+             WHERE (?1 IS NULL OR link = ?1) AND (?2 IS NULL OR uploader = ?2)
+             ORDER BY created DESC",
+        )?;
+
+        let mut records = uploads
+            .query_map(params![link, uploader], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, u64>(1)?, row.get::<_, String>(2)?, row.get::<_, u64>(3)?))
+            })?
+            .map(|row| {
+                let (link, size, metadata, downloads) = row?;
+
+                let mut metadata: UploadMetadata = serde_json::from_str(&metadata)
+                    .map_err(|e| AppError::DatabaseError(format!("Corrupted metadata of {}: {}", link, e)))?;
+
+                metadata.downloads = downloads;
+
+                Ok(UploadRecord { link, size, metadata, files: Vec::new() })
+            })
+            .collect::<Result<Vec<UploadRecord>, AppError>>()?;
+
+        let mut files = connection.prepare(
+            "SELECT files.link, files.name, files.size, files.sha256 FROM files JOIN uploads ON uploads.link = files.link
+             WHERE (?1 IS NULL OR files.link = ?1) AND (?2 IS NULL OR uploads.uploader = ?2)",
+        )?;
+
+        let mut files_by_link: HashMap<String, Vec<IndexedFile>> = HashMap::new();
+
+        for row in files.query_map(params![link, uploader], |row| {
+            Ok((row.get::<_, String>(0)?, IndexedFile { name: row.get(1)?, size: row.get(2)?, sha256: row.get(3)? }))
+        })? {
+            let (link, file) = row?;
+
+            files_by_link.entry(link).or_default().push(file);
+        }
+
+        for record in records.iter_mut() {
+            record.files = files_by_link.remove(&record.link).unwrap_or_default();
+        }
+
+        Ok(records)
+    }
+
+    /// Distinct names of the users that own at least one upload.
+    pub fn uploaders(&self) -> Result<Vec<String>, AppError> {
+        let connection = self.connection()?;
+
+        let mut statement = connection.prepare(
+            "SELECT DISTINCT uploader FROM uploads WHERE uploader IS NOT NULL ORDER BY uploader",
+        )?;
+
+        let uploaders = statement
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+
+        Ok(uploaders)
+    }
+
+    /// Size of the files uploaded by `uploader`, before compression.
+    pub fn usage(&self, uploader: &str) -> Result<u64, AppError> {
+        let usage = self.connection()?.query_row(
+            "SELECT COALESCE(SUM(files.size), 0) FROM files JOIN uploads ON uploads.link = files.link WHERE uploads.uploader = ?1",
+            params![uploader],
+            |row| row.get::<_, i64>(0),
+        )?;
+
+        Ok(usage as u64)
+    }
+
+    /// Links whose expiry is at or before `now`.
+    pub fn expired(&self, now: u64) -> Result<Vec<UploadRecord>, AppError> {
+        let links = {
+            let connection = self.connection()?;
+
+            let mut statement = connection.prepare(
+                "SELECT link FROM uploads WHERE expires_at IS NOT NULL AND expires_at <= ?1",
+            )?;
+
+            let links = statement
+                .query_map(params![now], |row| row.get::<_, String>(0))?
+                .collect::<Result<Vec<String>, _>>()?;
+
+            links
+        };
+
+        let mut records = Vec::new();
+
+        for link in links {
+            records.extend(self.get(&link)?);
+        }
+
+        Ok(records)
+    }
+
+    /// Counts a download of `link`, atomically refusing it once `max_downloads` is reached.
+    ///
+    /// Returns the number of downloads including this one, or `None` when the limit was already reached.
+    pub fn record_download(&self, link: &str) -> Result<Option<u64>, AppError> {
+        let mut connection = self.connection()?;
+
+        let transaction = connection.transaction()?;
+
+        let updated = transaction.execute(
+            "UPDATE uploads SET downloads = downloads + 1
+             WHERE link = ?1 AND (max_downloads IS NULL OR downloads < max_downloads)",
+            params![link],
+        )?;
+
+        if updated == 0 {
+            return Ok(None);
+        }
+
+        let downloads = transaction.query_row(
+            "SELECT downloads FROM uploads WHERE link = ?1",
+            params![link],
+            |row| row.get::<_, u64>(0),
+        )?;
+
+        transaction.commit()?;
+
+        Ok(Some(downloads))
+    }
+
+    /// Gives back a download recorded by `record_download`, for one that failed.
+    pub fn refund_download(&self, link: &str) -> Result<(), AppError> {
+        self.connection()?.execute(
+            "UPDATE uploads SET downloads = downloads - 1 WHERE link = ?1 AND downloads > 0",
+            params![link],
+        )?;
+
+        Ok(())
+    }
+
+    /// Replaces the metadata of an existing upload, keeping its download count.
+    pub fn update_metadata(&self, link: &str, metadata: &UploadMetadata) -> Result<(), AppError> {
+        let json = serde_json::to_string(metadata)
+            .map_err(|e| AppError::DatabaseError(e.to_string()))?;
+
+        let updated = self.connection()?.execute(
+            "UPDATE uploads SET expires_at = ?2, max_downloads = ?3, metadata = ?4 WHERE link = ?1",
+            params![link, metadata.expires_at, metadata.max_downloads, json],
+        )?;
+
+        if updated == 0 {
+            return Err(AppError::FileNotFound);
+        }
+
+        Ok(())
+    }
+
+    /// Removes an upload, returning the blobs no other upload references anymore.
+    pub fn remove(&self, link: &str) -> Result<Vec<String>, AppError> {
+        let mut connection = self.connection()?;
+
+        let transaction = connection.transaction()?;
+
+        let released = release_blobs(&transaction, link)?;
+
+        transaction.execute("DELETE FROM files WHERE link = ?1", params![link])?;
+        transaction.execute("DELETE FROM uploads WHERE link = ?1", params![link])?;
+
+        transaction.commit()?;
+
+        Ok(released)
+    }
+
+    /// Number of uploads referencing a blob.
+    pub fn blob_refs(&self, sha256: &str) -> Result<u64, AppError> {
+        let refs = self.connection()?
+            .query_row("SELECT refs FROM blobs WHERE sha256 = ?1", params![sha256], |row| row.get::<_, u64>(0))
+            .optional()?;
+
+        Ok(refs.unwrap_or(0))
+    }
+
+    pub fn insert_request(&self, request: &UploadRequest) -> Result<(), AppError> {
+        self.connection()?.execute(
+            "INSERT INTO upload_requests (token, creator, created, expires_at, max_size, max_files, label, uploads)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                request.token,
+                request.creator,
+                request.created,
+                request.expires_at,
+                request.max_size,
+                request.max_files,
+                request.label,
+                request.uploads
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn get_request(&self, token: &str) -> Result<Option<UploadRequest>, AppError> {
+        Ok(self.query_requests(Some(token), None)?.pop())
+    }
+
+    /// Upload requests created by `creator`, newest first.
+    pub fn list_requests(&self, creator: &str) -> Result<Vec<UploadRequest>, AppError> {
+        self.query_requests(None, Some(creator))
+    }
+
+    fn query_requests(&self, token: Option<&str>, creator: Option<&str>) -> Result<Vec<UploadRequest>, AppError> {
+        let connection = self.connection()?;
+
+        let mut statement = connection.prepare(
+            "SELECT token, creator, created, expires_at, max_size, max_files, label, uploads, files, size FROM upload_requests
+             WHERE (?1 IS NULL OR token = ?1) AND (?2 IS NULL OR creator = ?2)
+             ORDER BY created DESC",
+        )?;
+
+        let requests = statement
+            .query_map(params![token, creator], |row| {
+                Ok(UploadRequest {
+                    token: row.get(0)?,
+                    creator: row.get(1)?,
+                    created: row.get(2)?,
+                    expires_at: row.get(3)?,
+                    max_size: row.get(4)?,
+                    max_files: row.get(5)?,
+                    label: row.get(6)?,
+                    uploads: row.get(7)?,
+                    files: row.get(8)?,
+                    size: row.get(9)?,
+                })
+            })?
+            .collect::<Result<Vec<UploadRequest>, _>>()?;
+
+        Ok(requests)
+    }
+
+    /// Counts an upload of `files` files and `size` bytes, returning `false` when it would exceed the limits
+    /// of the request, given what other uploads already brought.
+    pub fn record_request_upload(&self, token: &str, files: u64, size: u64) -> Result<bool, AppError> {
+        let updated = self.connection()?.execute(
+            "UPDATE upload_requests SET uploads = uploads + 1, files = files + ?2, size = size + ?3
+             WHERE token = ?1 AND (max_files IS NULL OR files + ?2 <= max_files) AND (max_size IS NULL OR size + ?3 <= max_size)",
+            params![token, files, size],
+        )?;
+
+        Ok(updated > 0)
+    }
+
+    /// Removes an upload request of `creator`, returning whether it existed.
+    pub fn remove_request(&self, token: &str, creator: &str) -> Result<bool, AppError> {
+        let removed = self.connection()?.execute(
+            "DELETE FROM upload_requests WHERE token = ?1 AND creator = ?2",
+            params![token, creator],
+        )?;
+
+        Ok(removed > 0)
+    }
+
+    /// Removes the upload requests whose expiry is at or before `now`, returning how many were removed.
+    pub fn remove_expired_requests(&self, now: u64) -> Result<usize, AppError> {
+        let removed = self.connection()?.execute(
+            "DELETE FROM upload_requests WHERE expires_at IS NOT NULL AND expires_at <= ?1",
+            params![now],
+        )?;
+
+        Ok(removed)
+    }
+
+    pub fn insert_token(&self, token: &ApiToken, hash: &str) -> Result<(), AppError> {
+        self.connection()?.execute(
+            "INSERT INTO api_tokens (id, user, name, hash, created, expires_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![token.id, token.user, token.name, hash, token.created, token.expires_at],
+        )?;
+
+        Ok(())
+    }
+
+    /// A token with the hash of its secret.
+    pub fn get_token(&self, id: &str) -> Result<Option<(ApiToken, String)>, AppError> {
+        Ok(self.query_tokens(Some(id), None)?.pop())
+    }
+
+    /// Tokens of `user`, newest first.
+    pub fn list_tokens(&self, user: &str) -> Result<Vec<ApiToken>, AppError> {
+        Ok(self.query_tokens(None, Some(user))?.into_iter().map(|(token, _)| token).collect())
+    }
+
+    fn query_tokens(&self, id: Option<&str>, user: Option<&str>) -> Result<Vec<(ApiToken, String)>, AppError> {
+        let connection = self.connection()?;
+
+        let mut statement = connection.prepare(
+            "SELECT id, user, name, created, expires_at, last_used, hash FROM api_tokens
+             WHERE (?1 IS NULL OR id = ?1) AND (?2 IS NULL OR user = ?2)
+             ORDER BY created DESC",
+        )?;
+
+        let tokens = statement
+            .query_map(params![id, user], |row| {
+                Ok((ApiToken {
+                    id: row.get(0)?,
+                    user: row.get(1)?,
+                    name: row.get(2)?,
+                    created: row.get(3)?,
+                    expires_at: row.get(4)?,
+                    last_used: row.get(5)?,
+                }, row.get(6)?))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(tokens)
+    }
+
+    pub fn touch_token(&self, id: &str, now: u64) -> Result<(), AppError> {
+        self.connection()?.execute("UPDATE api_tokens SET last_used = ?2 WHERE id = ?1", params![id, now])?;
+
+        Ok(())
+    }
+
+    /// Removes a token of `user`, returning whether it existed.
+    pub fn remove_token(&self, id: &str, user: &str) -> Result<bool, AppError> {
+        let removed = self.connection()?.execute(
+            "DELETE FROM api_tokens WHERE id = ?1 AND user = ?2",
+            params![id, user],
+        )?;
+
+        Ok(removed > 0)
+    }
+
+    pub fn clear(&self) -> Result<(), AppError> {
+        self.connection()?.execute_batch("DELETE FROM files; DELETE FROM uploads; DELETE FROM blobs;")?;
+
+        Ok(())
+    }
+}
+
+/// Applies the migrations `schema` is missing, returning its version before.
+fn migrate(connection: &mut Connection, schema: &str, migrations: &[&str]) -> Result<usize, AppError> {
+    let version: usize = connection.query_row(&format!("PRAGMA {}.user_version", schema), [], |row| row.get(0))?;
+
+    for (number, migration) in migrations.iter().enumerate().skip(version) {
+        let transaction = connection.transaction()?;
+
+        transaction.execute_batch(migration)?;
+        transaction.execute_batch(&format!("PRAGMA {}.user_version = {}", schema, number + 1))?;
+
+        transaction.commit()?;
+    }
+
+    Ok(version)
+}
+
+/// Drops the blob references of `link`, returning the blobs left without any.
+fn release_blobs(transaction: &Transaction, link: &str) -> Result<Vec<String>, AppError> {
+    let metadata = transaction
+        .query_row("SELECT metadata FROM uploads WHERE link = ?1", params![link], |row| row.get::<_, String>(0))
+        .optional()?;
+
+    let blobs = match metadata.map(|metadata| serde_json::from_str::<UploadMetadata>(&metadata)) {
+        Some(Ok(metadata)) => metadata.blobs.unwrap_or_default(),
+        Some(Err(e)) => return Err(AppError::DatabaseError(format!("Corrupted metadata of {}: {}", link, e))),
+        None => return Ok(Vec::new()),
+    };
+
+    let mut released = Vec::new();
+
+    for blob in blobs {
+        transaction.execute("UPDATE blobs SET refs = refs - 1 WHERE sha256 = ?1", params![blob.sha256])?;
+
+        if transaction.execute("DELETE FROM blobs WHERE sha256 = ?1 AND refs <= 0", params![blob.sha256])? > 0 {
+            released.push(blob.sha256);
+        }
+    }
+
+    Ok(released)
+}
+
+#[cfg(test)]
+fn test_index() -> UploadIndex {
+    let (index, created) = UploadIndex::open(Path::new(":memory:"), Path::new(":memory:")).unwrap();
+
+    assert!(created);
+
+    index
+}
+
+#[test]
+fn index_downloads_work() {
+    let index = test_index();
+
+    index.insert(&UploadRecord {
+        link: "klmnopqrst".to_string(),
+        size: 10,
+        metadata: UploadMetadata { created: 1, max_downloads: Some(1), ..UploadMetadata::default() },
+        files: vec![],
+    }).unwrap();
+
+    assert_eq!(index.record_download("klmnopqrst").unwrap(), Some(1));
+    assert_eq!(index.record_download("klmnopqrst").unwrap(), None);
+    assert_eq!(index.get("klmnopqrst").unwrap().unwrap().metadata.downloads, 1);
+
+    index.remove("klmnopqrst").unwrap();
+
+    assert!(index.list(None).unwrap().is_empty());
+}
+
+#[test]
+fn index_uploaders_work() {
+    let index = test_index();
+
+    index.insert(&UploadRecord {
+        link: "abcdefghij".to_string(),
+        size: 10,
+        metadata: UploadMetadata { created: 1, expires_at: Some(5), uploader: Some("alice".to_string()), ..UploadMetadata::default() },
+        files: vec![IndexedFile { name: "a.txt".to_string(), size: 3, sha256: None }],
+    }).unwrap();
+
+    let record = index.get("abcdefghij").unwrap().unwrap();
+
+    assert_eq!(record.files.len(), 1);
+    assert_eq!(index.expired(4).unwrap().len(), 0);
+    assert_eq!(index.expired(5).unwrap().len(), 1);
+    assert_eq!(index.usage("alice").unwrap(), 3);
+    assert_eq!(index.usage("bob").unwrap(), 0);
+    assert_eq!(index.uploaders().unwrap(), vec!["alice".to_string()]);
+    assert_eq!(index.list(Some("bob")).unwrap().len(), 0);
+}
+
+#[test]
+fn index_blobs_work() {
+    let index = test_index();
+
+    let blob = |name: &str, sha256: &str| crate::metadata::BlobFile { name: name.to_string(), size: 3, sha256: sha256.to_string(), crc32: 0 };
+
+    for (link, blobs) in [("uvwxyzabcd", vec![blob("a.txt", "aa"), blob("b.txt", "bb")]), ("efghijklmn", vec![blob("c.txt", "aa")])] {
+        index.insert(&UploadRecord {
+            link: link.to_string(),
+            size: 3,
+            metadata: UploadMetadata { created: 1, blobs: Some(blobs), ..UploadMetadata::default() },
+            files: vec![],
+        }).unwrap();
+    }
+
+    assert_eq!(index.blob_refs("aa").unwrap(), 2);
+    assert_eq!(index.remove("uvwxyzabcd").unwrap(), vec!["bb".to_string()]);
+    assert_eq!(index.blob_refs("aa").unwrap(), 1);
+    assert_eq!(index.remove("efghijklmn").unwrap(), vec!["aa".to_string()]);
+}
+
+#[test]
+fn index_requests_work() {
+    let index = test_index();
+
+    index.insert_request(&UploadRequest {
+        token: "requestabc".to_string(),
+        creator: "alice".to_string(),
+        created: 1,
+        expires_at: Some(5),
+        max_size: Some(1024),
+        max_files: None,
+        label: None,
+        uploads: 0,
+        files: 0,
+        size: 0,
+    }).unwrap();
+
+    assert!(index.record_request_upload("requestabc", 2, 1000).unwrap());
+    assert!(!index.record_request_upload("requestabc", 1, 25).unwrap());
+    assert!(index.record_request_upload("requestabc", 1, 24).unwrap());
+
+    let request = index.get_request("requestabc").unwrap().unwrap();
+
+    assert_eq!((request.uploads, request.files, request.size), (2, 3, 1024));
+    assert_eq!(request.remaining_size(), Some(0));
+    assert_eq!(request.remaining_files(), None);
+    assert_eq!(index.list_requests("bob").unwrap().len(), 0);
+    assert!(!index.remove_request("requestabc", "bob").unwrap());
+    assert_eq!(index.remove_expired_requests(4).unwrap(), 0);
+    assert_eq!(index.remove_expired_requests(5).unwrap(), 1);
+    assert!(index.list_requests("alice").unwrap().is_empty());
+}
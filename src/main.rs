@@ -2,37 +2,55 @@
 extern crate log;
 
 
-use actix_files::NamedFile;
+use std::collections::HashMap;
+use std::io::IsTerminal;
+use std::sync::Arc;
+
 use actix_multipart::Multipart;
-use actix_web::{App, Error, HttpResponse, HttpServer, web};
+use actix_web::{App, Error, guard, HttpMessage, HttpRequest, HttpResponse, HttpServer, web};
 use actix_web::error::ErrorInternalServerError;
-use actix_web::middleware::{Compress, Logger};
-use serde::{Deserialize, Serialize};
+use actix_web::http::{header, Method, StatusCode};
+use actix_web::middleware::{Compress, Condition, DefaultHeaders, Logger};
+#[cfg(unix)]
+use actix_web::rt::signal::unix::{signal, SignalKind};
+use serde::Deserialize;
 use tera::Context;
 
 use crate::app::AppData;
-use crate::auth_middleware::BasicAuth;
-use crate::upload::DisplayDirectories;
+use crate::app_configs::AuthStrategy;
+use crate::auth_middleware::{AuthenticatedUser, BasicAuth, Role};
+use crate::authenticator::PasswdFileAuthenticator;
+use crate::errors::AppError;
+use crate::session::{SESSION_COOKIE, SessionManager, UNLOCK_COOKIE};
+use crate::tokens::ApiTokens;
+use crate::upload::{DisplayDirectories, LinkKey, RequestOptions, UploadManager};
 
+mod api;
 mod app;
+mod blobs;
+mod index;
 mod auth_middleware;
 mod errors;
 mod upload;
 mod utils;
 mod app_configs;
 mod authenticator;
+mod csrf;
+mod metadata;
+mod oidc;
+mod password;
+mod session;
+mod storage;
+mod throttle;
+mod tokens;
+mod tus;
+mod tls;
+mod zip_stream;
 
-#[derive(Debug, Serialize)]
-struct FileInfo {
-    filename: String,
-    created: String,
-    size: u64,
-}
-
-async fn save_file(payload: Multipart, data: web::Data<AppData>) -> Result<HttpResponse, Error> {
+async fn save_file(req: HttpRequest, payload: Multipart, data: web::Data<AppData>) -> Result<HttpResponse, Error> {
     let manager = data.manager();
 
-    let _ = manager.store(payload).await?;
+    let _ = manager.store(payload, AuthenticatedUser::from_request(&req)).await?;
 
     Ok(HttpResponse::SeeOther().header("Location", "/").finish())
 }
@@ -40,15 +58,158 @@ async fn save_file(payload: Multipart, data: web::Data<AppData>) -> Result<HttpR
 #[derive(Debug, Deserialize)]
 struct FilePath {
     file: String,
+    /// Name of a single file inside the archive, the whole archive when missing.
+    path: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ShareQuery {
+    /// Skips the landing page and sends the archive, as done for clients that do not ask for html.
+    download: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PasswordForm {
+    password: String,
+}
+
+/// Header scripts can use to send the password of a protected link.
+const SHARE_PASSWORD_HEADER: &str = "X-Share-Password";
+
+/// Password of a protected link sent by a script, or the cookie set once it was typed in the password page.
+fn link_key(req: &HttpRequest, link: &str, auth: &BasicAuth) -> LinkKey {
+    if let Some(password) = req.headers().get(SHARE_PASSWORD_HEADER).and_then(|h| h.to_str().ok()) {
+        return LinkKey::Password(password.to_string());
+    }
+
+    match req.cookie(UNLOCK_COOKIE) {
+        Some(cookie) if auth.sessions().is_unlocked(cookie.value(), link) => LinkKey::Unlocked,
+        _ => LinkKey::Nothing,
+    }
 }
 
 async fn download_file(
+    req: HttpRequest,
     path: web::Path<FilePath>,
+    query: web::Query<ShareQuery>,
     data: web::Data<AppData>,
-) -> Result<NamedFile, Error> {
-    data.manager()
-        .get_file_from_link(path.file.as_str())
-        .map_err(|e| e.into())
+    auth: web::Data<BasicAuth>,
+) -> Result<HttpResponse, Error> {
+    let key = link_key(&req, &path.file, &auth);
+
+    let accepts_html = req.headers()
+        .get(header::ACCEPT)
+        .and_then(|h| h.to_str().ok())
+        .map(|accept| accept.contains("text/html"))
+        .unwrap_or(false);
+
+    if path.path.is_none() && query.download.is_none() && accepts_html {
+        return share_page(&req, &path.file, key, &data).await;
+    }
+
+    serve_file(&req, &path, key, &data).await
+}
+
+/// Lists the files of a link, with a button to download all of them.
+async fn share_page(req: &HttpRequest, link: &str, key: LinkKey, data: &AppData) -> Result<HttpResponse, Error> {
+    let manager = data.manager();
+    let link = link.to_string();
+
+    let record = match web::block(move || manager.authorize(&link, key)).await.map_err(AppError::from) {
+        Ok(record) => record,
+        Err(AppError::PasswordRequired) => return password_page(req, StatusCode::UNAUTHORIZED, None, data),
+        Err(AppError::InvalidPassword) => return password_page(req, StatusCode::FORBIDDEN, Some("Wrong password"), data),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut context = Context::new();
+
+    context.insert("link", &record.link);
+    context.insert("files", &record.files);
+    context.insert("size", &record.files.iter().map(|file| file.size).sum::<u64>());
+    context.insert("created", &record.metadata.created);
+    context.insert("expires_at", &record.metadata.expires_at);
+    context.insert("remaining_downloads", &record.metadata.remaining_downloads());
+    context.insert("uploader", &record.metadata.uploader);
+    context.insert("message", &record.metadata.message);
+
+    let content = data
+        .templates()
+        .render("share.html", &context)
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .header(header::VARY, "Accept")
+        .body(content))
+}
+
+/// Checks the password typed for a link, then goes back to what was asked with the link unlocked for a while: its
+/// landing page, or a single file.
+async fn unlock_file(
+    req: HttpRequest,
+    path: web::Path<FilePath>,
+    form: web::Form<PasswordForm>,
+    data: web::Data<AppData>,
+    auth: web::Data<BasicAuth>,
+) -> Result<HttpResponse, Error> {
+    let manager = data.manager();
+    let link = path.file.clone();
+    let password = form.into_inner().password;
+
+    let result = web::block(move || manager.authorize(&link, LinkKey::Password(password)).map(|_| ())).await;
+
+    match result.map_err(AppError::from) {
+        Ok(()) => Ok(HttpResponse::SeeOther()
+            .header(header::LOCATION, req.path())
+            .cookie(auth.sessions().unlock(&path.file, req.connection_info().scheme() == "https"))
+            .finish()),
+        Err(AppError::InvalidPassword) => password_page(&req, StatusCode::FORBIDDEN, Some("Wrong password"), &data),
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn serve_file(req: &HttpRequest, path: &FilePath, key: LinkKey, data: &AppData) -> Result<HttpResponse, Error> {
+    let manager = data.manager();
+    let (link, name) = (path.file.clone(), path.path.clone());
+
+    // HEAD requests send no content, and do not use up limited links.
+    let count = req.method() != Method::HEAD;
+
+    let result = web::block(move || {
+        let mut download = match name {
+            Some(ref name) => manager.get_entry_from_link(&link, name, key)?,
+            None => manager.get_file_from_link(&link, key)?,
+        };
+
+        if count {
+            download.count()?;
+        }
+
+        Ok::<_, AppError>(download)
+    }).await;
+
+    match result.map_err(AppError::from) {
+        Ok(download) => download.into_response(req),
+        Err(AppError::PasswordRequired) => password_page(req, StatusCode::UNAUTHORIZED, None, data),
+        Err(AppError::InvalidPassword) => password_page(req, StatusCode::FORBIDDEN, Some("Wrong password"), data),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Asks for the password of a protected link, posting it back to the requested url.
+fn password_page(req: &HttpRequest, status: StatusCode, error: Option<&str>, data: &AppData) -> Result<HttpResponse, Error> {
+    let mut context = Context::new();
+
+    context.insert("action", req.path());
+    context.insert("error", &error);
+
+    let content = data
+        .templates()
+        .render("password.html", &context)
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(HttpResponse::build(status).content_type("text/html; charset=utf-8").body(content))
 }
 
 async fn index(data: web::Data<AppData>) -> Result<HttpResponse, Error> {
@@ -62,13 +223,306 @@ async fn index(data: web::Data<AppData>) -> Result<HttpResponse, Error> {
     Ok(HttpResponse::Ok().body(index_content))
 }
 
-async fn list_files(data: web::Data<AppData>) -> Result<HttpResponse, Error> {
+#[derive(Debug, Deserialize)]
+struct RequestPath {
+    token: String,
+}
+
+/// Upload page of an upload request, for senders without an account.
+async fn request_page(path: web::Path<RequestPath>, data: web::Data<AppData>) -> Result<HttpResponse, Error> {
+    let request = data.manager().get_request(&path.token)?;
+
+    let mut context = Context::new();
+
+    context.insert("request", &request);
+
+    let content = data
+        .templates()
+        .render("home.html", &context)
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().body(content))
+}
+
+async fn save_requested_file(path: web::Path<RequestPath>, payload: Multipart, data: web::Data<AppData>) -> Result<HttpResponse, Error> {
+    let _ = data.manager().store_requested(payload, &path.token).await?;
+
+    Ok(HttpResponse::SeeOther().header("Location", format!("/drop/{}", path.token)).finish())
+}
+
+async fn create_request(req: HttpRequest, form: web::Form<HashMap<String, String>>, data: web::Data<AppData>) -> Result<HttpResponse, Error> {
+    csrf::verify(&req, form.get("csrf_token").map(|token| token.as_str()).unwrap_or_default())?;
+
+    let creator = AuthenticatedUser::from_request(&req).ok_or(AppError::FileNotFound)?;
+
+    data.manager().create_request(&creator, RequestOptions::from_fields(&form)?)?;
+
+    Ok(back_to_files())
+}
+
+async fn delete_request(
+    req: HttpRequest,
+    path: web::Path<RequestPath>,
+    form: web::Form<DeleteForm>,
+    data: web::Data<AppData>,
+) -> Result<HttpResponse, Error> {
+    csrf::verify(&req, &form.csrf_token)?;
+
+    let creator = AuthenticatedUser::from_request(&req).ok_or(AppError::FileNotFound)?;
+
+    data.manager().delete_request(&path.token, &creator)?;
+
+    Ok(back_to_files())
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenForm {
+    csrf_token: String,
+    name: String,
+    expires_in: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenPath {
+    id: String,
+}
+
+async fn create_token(req: HttpRequest, form: web::Form<TokenForm>, data: web::Data<AppData>) -> Result<HttpResponse, Error> {
+    csrf::verify(&req, &form.csrf_token)?;
+
+    let user = AuthenticatedUser::from_request(&req).ok_or(AppError::FileNotFound)?;
+    let expires_in = utils::parse_duration(&form.expires_in).map_err(AppError::InvalidRequest)?;
+
+    let (token, value) = data.tokens().create(&user, &form.name, expires_in)?;
+
+    let mut context = Context::new();
+
+    context.insert("token", &token);
+    context.insert("value", &value);
+
+    let content = data
+        .templates()
+        .render("token.html", &context)
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok()
+        .set_header(header::CACHE_CONTROL, "no-store")
+        .body(content))
+}
+
+async fn revoke_token(
+    req: HttpRequest,
+    path: web::Path<TokenPath>,
+    form: web::Form<DeleteForm>,
+    data: web::Data<AppData>,
+) -> Result<HttpResponse, Error> {
+    csrf::verify(&req, &form.csrf_token)?;
+
+    let user = AuthenticatedUser::from_request(&req).ok_or(AppError::FileNotFound)?;
+
+    data.tokens().revoke(&user, &path.id)?;
+
+    Ok(back_to_files())
+}
+
+#[derive(Debug, Deserialize)]
+struct LoginQuery {
+    next: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LoginForm {
+    csrf_token: String,
+    username: String,
+    password: String,
+    next: Option<String>,
+}
+
+/// Where to go after logging in, only paths of this site.
+fn login_target(next: Option<&str>) -> &str {
+    next.filter(|next| next.starts_with('/') && !next.starts_with("//") && !next.starts_with("/\\"))
+        .unwrap_or("/files")
+}
+
+fn login_page(
+    req: &HttpRequest,
+    status: StatusCode,
+    next: Option<&str>,
+    error: Option<&str>,
+    auth: &BasicAuth,
+    data: &AppData,
+) -> Result<HttpResponse, Error> {
+    let (csrf_token, csrf_cookie) = csrf::token(req);
+
+    let mut context = Context::new();
+
+    context.insert("csrf_token", &csrf_token);
+    context.insert("next", login_target(next));
+    context.insert("next_query", &utils::percent_encode(login_target(next)));
+    context.insert("error", &error);
+    context.insert("sso", &auth.oidc().map(|oidc| oidc.name().to_string()));
+
+    let content = data
+        .templates()
+        .render("login.html", &context)
+        .map_err(ErrorInternalServerError)?;
+
+    let mut response = HttpResponse::build(status);
+
+    if let Some(cookie) = csrf_cookie {
+        response.cookie(cookie);
+    }
+
+    Ok(response.content_type("text/html; charset=utf-8").body(content))
+}
+
+async fn show_login(req: HttpRequest, query: web::Query<LoginQuery>, auth: web::Data<BasicAuth>, data: web::Data<AppData>) -> Result<HttpResponse, Error> {
+    login_page(&req, StatusCode::OK, query.next.as_deref(), None, &auth, &data)
+}
+
+/// Sends the browser to the OpenID Connect provider.
+async fn start_oidc_login(req: HttpRequest, query: web::Query<LoginQuery>, auth: web::Data<BasicAuth>, data: web::Data<AppData>) -> Result<HttpResponse, Error> {
+    let oidc = auth.oidc().ok_or(AppError::FileNotFound)?;
+    let next = query.into_inner().next;
+    let target = next.clone();
+
+    match web::block(move || oidc.start(target.as_deref())).await.map_err(AppError::from) {
+        Ok((url, state)) => {
+            Ok(HttpResponse::SeeOther()
+                .header(header::LOCATION, url)
+                .cookie(oidc::state_cookie(state, req.connection_info().scheme() == "https"))
+                .finish())
+        }
+        Err(e) => {
+            error!("Error starting a single sign-on login: {}", e);
+
+            login_page(&req, StatusCode::BAD_GATEWAY, next.as_deref(), Some("Single sign-on is not available"), &auth, &data)
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct OidcCallbackQuery {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+}
+
+/// Where the OpenID Connect provider sends the browser back, logging the user in.
+async fn finish_oidc_login(
+    req: HttpRequest,
+    query: web::Query<OidcCallbackQuery>,
+    auth: web::Data<BasicAuth>,
+    data: web::Data<AppData>,
+) -> Result<HttpResponse, Error> {
+    let oidc = auth.oidc().ok_or(AppError::FileNotFound)?;
+    let query = query.into_inner();
+    let cookie_state = req.cookie(oidc::STATE_COOKIE).map(|cookie| cookie.value().to_string());
+
+    let result = match (query.code, query.state, query.error) {
+        (Some(code), Some(state), None) => web::block(move || oidc.finish(&state, cookie_state.as_deref(), &code))
+            .await
+            .map_err(AppError::from),
+        (_, _, error) => Err(AppError::Forbidden(format!("provider answered {}", error.unwrap_or_default()))),
+    };
+
+    match result {
+        Ok((user, next)) => {
+            info!("{} logged in with single sign-on{}", user.name, if user.is_admin() { " as admin" } else { "" });
+
+            let cookie = auth.sessions().create(&user.name, user.role, req.connection_info().scheme() == "https");
+
+            Ok(HttpResponse::SeeOther()
+                .header(header::LOCATION, login_target(next.as_deref()))
+                .cookie(cookie)
+                .del_cookie(&oidc::state_removal_cookie())
+                .finish())
+        }
+        Err(e) => {
+            warn!("Single sign-on login refused: {}", e);
+
+            login_page(&req, StatusCode::FORBIDDEN, None, Some("Single sign-on failed"), &auth, &data)
+        }
+    }
+}
+
+async fn login(req: HttpRequest, form: web::Form<LoginForm>, auth: web::Data<BasicAuth>, data: web::Data<AppData>) -> Result<HttpResponse, Error> {
+    csrf::verify(&req, &form.csrf_token)?;
+
+    let next = form.next.as_deref();
+
+    let (checker, address, username, password) = (auth.clone(), req.peer_addr().map(|addr| addr.ip()), form.username.clone(), form.password.clone());
+
+    let checked = web::block(move || Ok::<_, AppError>(checker.authenticate(address, &username, &password)))
+        .await
+        .map_err(AppError::from)?;
+
+    match checked {
+        Ok(true) => {
+            info!("{} logged in", form.username);
+
+            let cookie = auth.sessions().create(&form.username, Role::User, req.connection_info().scheme() == "https");
+
+            Ok(HttpResponse::SeeOther()
+                .header(header::LOCATION, login_target(next))
+                .cookie(cookie)
+                .finish())
+        }
+        Ok(false) => login_page(&req, StatusCode::UNAUTHORIZED, next, Some("Wrong username or password"), &auth, &data),
+        Err(retry_after) => {
+            let seconds = retry_after.as_secs_f64().ceil() as u64;
+            let error = format!("Too many failed logins, try again in {} seconds", seconds);
+
+            let mut response = login_page(&req, StatusCode::TOO_MANY_REQUESTS, next, Some(&error), &auth, &data)?;
+
+            response.headers_mut().insert(header::RETRY_AFTER, header::HeaderValue::from(seconds));
+
+            Ok(response)
+        }
+    }
+}
+
+async fn logout(req: HttpRequest, form: web::Form<DeleteForm>, auth: web::Data<BasicAuth>) -> Result<HttpResponse, Error> {
+    csrf::verify(&req, &form.csrf_token)?;
+
+    if let Some(cookie) = req.cookie(SESSION_COOKIE) {
+        auth.sessions().revoke(cookie.value());
+    }
+
+    Ok(HttpResponse::SeeOther()
+        .header(header::LOCATION, "/login")
+        .del_cookie(&SessionManager::removal_cookie())
+        .finish())
+}
+
+#[derive(Debug, Deserialize)]
+struct FilesQuery {
+    owner: Option<String>,
+}
+
+async fn list_files(req: HttpRequest, query: web::Query<FilesQuery>, data: web::Data<AppData>) -> Result<HttpResponse, Error> {
     let mut context = Context::new();
 
+    let (csrf_token, csrf_cookie) = csrf::token(&req);
+
+    let owner = query.owner.as_deref().filter(|owner| !owner.is_empty());
+
     context.insert(
         "entries",
-        &DisplayDirectories::from(&data.manager().list_directory()?),
+        &DisplayDirectories::from(&data.manager().list_directory(owner)?),
     );
+    context.insert("owner", &owner);
+    context.insert("owners", &data.manager().uploaders()?);
+    context.insert("csrf_token", &csrf_token);
+
+    if let Some(user) = AuthenticatedUser::get(&req) {
+        context.insert("user", &user.name);
+        context.insert("is_admin", &user.is_admin());
+        context.insert("has_session", &req.cookie(SESSION_COOKIE).is_some());
+        context.insert("quota", &data.manager().quota(&user.name)?);
+        context.insert("requests", &data.manager().list_requests(&user.name)?);
+        context.insert("tokens", &data.tokens().list(&user.name)?);
+    }
 
     let index_content = data
         .templates()
@@ -83,23 +537,249 @@ async fn list_files(data: web::Data<AppData>) -> Result<HttpResponse, Error> {
             )
         })?;
 
-    Ok(HttpResponse::Ok().body(index_content))
+    let mut response = HttpResponse::Ok();
+
+    if let Some(cookie) = csrf_cookie {
+        response.cookie(cookie);
+    }
+
+    Ok(response.body(index_content))
 }
 
 #[derive(Debug, Deserialize)]
-struct LinkPath {
+struct UploadPath {
     link: String,
 }
 
+#[derive(Debug, Deserialize)]
+struct DeleteForm {
+    csrf_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ExpiryForm {
+    csrf_token: String,
+    expires_in: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct LabelForm {
+    csrf_token: String,
+    label: String,
+}
+
+fn back_to_files() -> HttpResponse {
+    HttpResponse::SeeOther().header("Location", "/files").finish()
+}
+
+async fn delete_upload(
+    req: HttpRequest,
+    path: web::Path<UploadPath>,
+    form: web::Form<DeleteForm>,
+    data: web::Data<AppData>,
+) -> Result<HttpResponse, Error> {
+    csrf::verify(&req, &form.csrf_token)?;
+
+    let manager = data.manager();
+    let link = path.into_inner().link;
+    let user = AuthenticatedUser::get(&req);
+
+    web::block(move || manager.delete(&link, user.as_ref())).await.map_err(AppError::from)?;
+
+    Ok(back_to_files())
+}
+
+async fn set_upload_expiry(
+    req: HttpRequest,
+    path: web::Path<UploadPath>,
+    form: web::Form<ExpiryForm>,
+    data: web::Data<AppData>,
+) -> Result<HttpResponse, Error> {
+    csrf::verify(&req, &form.csrf_token)?;
+
+    let expires_in = utils::parse_duration(&form.expires_in).map_err(AppError::InvalidRequest)?;
+
+    let manager = data.manager();
+    let link = path.into_inner().link;
+    let user = AuthenticatedUser::get(&req);
+
+    web::block(move || manager.set_expiry(&link, expires_in, user.as_ref())).await.map_err(AppError::from)?;
+
+    Ok(back_to_files())
+}
+
+async fn set_upload_label(
+    req: HttpRequest,
+    path: web::Path<UploadPath>,
+    form: web::Form<LabelForm>,
+    data: web::Data<AppData>,
+) -> Result<HttpResponse, Error> {
+    csrf::verify(&req, &form.csrf_token)?;
+
+    let label = Some(form.label.trim().to_string()).filter(|label| !label.is_empty());
+
+    let manager = data.manager();
+    let link = path.into_inner().link;
+    let user = AuthenticatedUser::get(&req);
+
+    web::block(move || manager.set_label(&link, label, user.as_ref())).await.map_err(AppError::from)?;
+
+    Ok(back_to_files())
+}
+
+/// `fshare rebuild-index [config]`: recreates the upload index from the stored archives.
+fn rebuild_index(config_file: Option<&String>) -> std::io::Result<()> {
+    let application_configurations = app_configs::ApplicationConfigurations::from_config_file(config_file)
+        .expect("Error loading application properties");
+
+    let indexed = UploadManager::from_configs(application_configurations.upload_configs())
+        .and_then(|manager| manager.rebuild_index())
+        .map_err(|e| std::io::Error::other(e.to_string()))?;
+
+    info!("Upload index rebuilt with {} uploads", indexed);
+
+    Ok(())
+}
+
+/// `fshare user add <name> [config]`: adds a user with a hashed password to the users file.
+fn user_command(args: &[String]) -> std::io::Result<()> {
+    let username = match (args.first().map(|arg| arg.as_str()), args.get(1)) {
+        (Some("add"), Some(username)) => username,
+        _ => {
+            eprintln!("Usage: fshare user add <name> [config]");
+            std::process::exit(2);
+        }
+    };
+
+    let application_configurations = app_configs::ApplicationConfigurations::from_config_file(args.get(2))
+        .expect("Error loading application properties");
+
+    let users_file = match application_configurations.server_configs().auth_strategy() {
+        AuthStrategy::File { users_file, .. } => users_file.clone(),
+        _ => {
+            eprintln!("Users can only be added with auth_strategy=auth_file");
+            std::process::exit(2);
+        }
+    };
+
+    let password = if std::io::stdin().is_terminal() {
+        let password = rpassword::prompt_password(format!("Password for {}: ", username))?;
+
+        if password != rpassword::prompt_password("Repeat password: ")? {
+            eprintln!("Passwords do not match");
+            std::process::exit(1);
+        }
+
+        password
+    } else {
+        let mut password = String::new();
+        std::io::stdin().read_line(&mut password)?;
+        password.trim_end_matches(['\r', '\n']).to_string()
+    };
+
+    if password.is_empty() {
+        eprintln!("Empty password");
+        std::process::exit(1);
+    }
+
+    PasswdFileAuthenticator::set_password(&users_file, username, &password)
+        .map_err(|e| std::io::Error::other(e.to_string()))?;
+
+    println!("User {} saved in {}", username, users_file.display());
+
+    Ok(())
+}
+
+/// `fshare token add|list|revoke ...`: manages the api tokens of a user.
+fn token_command(args: &[String]) -> std::io::Result<()> {
+    const USAGE: &str = "Usage: fshare token add <user> <name> [--expires <duration>] [config]\n       \
+                         fshare token list <user> [config]\n       \
+                         fshare token revoke <user> <id> [config]";
+
+    let mut args = args.to_vec();
+
+    let expires_in = match args.iter().position(|arg| arg == "--expires") {
+        Some(position) if position + 1 < args.len() => {
+            let value = args.remove(position + 1);
+            args.remove(position);
+
+            match utils::parse_duration(&value) {
+                Ok(expires_in) => expires_in,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(2);
+                }
+            }
+        }
+        Some(_) => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+        None => None,
+    };
+
+    let (command, user) = match (args.first(), args.get(1)) {
+        (Some(command), Some(user)) => (command.as_str(), user.as_str()),
+        _ => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    let config_file = match command {
+        "list" => args.get(2),
+        _ => args.get(3),
+    };
+
+    let application_configurations = app_configs::ApplicationConfigurations::from_config_file(config_file)
+        .expect("Error loading application properties");
+
+    let tokens = UploadManager::from_configs(application_configurations.upload_configs())
+        .map(|manager| ApiTokens::new(manager.index()))
+        .map_err(|e| std::io::Error::other(e.to_string()))?;
+
+    let result = match (command, args.get(2)) {
+        ("add", Some(name)) => tokens.create(user, name, expires_in).map(|(token, value)| {
+            println!("Token {} ({}) of {}, shown only once:\n{}", token.id, token.name, user, value);
+        }),
+        ("list", _) => tokens.list(user).map(|tokens| {
+            for token in tokens {
+                let time = |timestamp: Option<u64>| timestamp
+                    .and_then(|timestamp| chrono::DateTime::from_timestamp(timestamp as i64, 0))
+                    .map(|date| date.format("%Y-%m-%d %H:%M").to_string())
+                    .unwrap_or_else(|| "-".to_string());
+
+                println!("{}\t{}\tcreated {}\texpires {}\tlast used {}",
+                         token.id, token.name, time(Some(token.created)), time(token.expires_at), time(token.last_used));
+            }
+        }),
+        ("revoke", Some(id)) => tokens.revoke(user, id).map(|_| println!("Token {} revoked", id)),
+        _ => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    result.map_err(|e| std::io::Error::other(e.to_string()))
+}
+
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     std::env::set_var("RUST_LOG", "fshare=debug,actix_web=info");
     env_logger::init();
 
     let args: Vec<_> = std::env::args().collect();
+
+    match args.get(1).map(|arg| arg.as_str()) {
+        Some("rebuild-index") => return rebuild_index(args.get(2)),
+        Some("user") => return user_command(&args[2..]),
+        Some("token") => return token_command(&args[2..]),
+        _ => {}
+    }
+
     let application_configurations = app_configs::ApplicationConfigurations::from_config_file(
         args.get(1)
-    ).expect(format!("Error loading application properties").as_str());
+    ).expect("Error loading application properties");
 
     let addr = format!("{}:{}",
                        application_configurations.server_configs().host(),
@@ -110,30 +790,231 @@ async fn main() -> std::io::Result<()> {
     let log_format = application_configurations.server_configs().log_format().to_string();
     let number_of_threads = application_configurations.server_configs().number_thread() as usize;
 
-    let auth_middleware = BasicAuth::new(application_configurations.server_configs().auth_strategy())
+    let upload_requires_auth = application_configurations.upload_configs().require_auth();
+
+    let cleanup_interval = application_configurations.upload_configs().cleanup_interval();
+
+    let server_configs = application_configurations.server_configs();
+
+    let certificate_resolver = server_configs.tls()
+        .map(|tls| tls::CertificateResolver::new(tls.certificate(), tls.private_key()).map(Arc::new))
+        .transpose()
+        .expect("Unable to load the tls certificate");
+
+    let redirect_addr = server_configs.http_redirect_port()
+        .map(|port| format!("{}:{}", server_configs.host(), port));
+
+    let https_port = server_configs.port();
+
+    let auth_configs = server_configs.clone();
+
+    let app_data = web::Data::new(AppData::new(application_configurations)
+        .expect("Error creating application properties"));
+
+    let auth_middleware = BasicAuth::new(&auth_configs, app_data.tokens())
         .expect("Unable to starting authentication middleware");
 
-    HttpServer::new(move || {
+    let auth_data = web::Data::new(auth_middleware.clone());
+
+    let reaper_manager = app_data.manager();
+
+    let reaper_data = app_data.clone();
+
+    actix_web::rt::spawn(async move {
+        let mut interval = actix_web::rt::time::interval(cleanup_interval);
+
+        loop {
+            interval.tick().await;
+
+            let manager = reaper_manager.clone();
+
+            match web::block(move || manager.remove_expired()).await {
+                Ok(removed) if !removed.is_empty() => {
+                    let reclaimed: u64 = removed.iter().map(|(_, size)| size).sum();
+
+                    info!("Removed {} expired uploads ({}), reclaimed {} bytes",
+                          removed.len(),
+                          removed.iter().map(|(link, _)| link.as_str()).collect::<Vec<_>>().join(", "),
+                          reclaimed);
+                }
+                Ok(_) => {}
+                Err(e) => error!("Error removing expired uploads: {:?}", e),
+            }
+
+            let data = reaper_data.clone();
+
+            match web::block(move || data.tus().remove_expired()).await {
+                Ok(0) => {}
+                Ok(removed) => info!("Removed {} abandoned resumable uploads", removed),
+                Err(e) => error!("Error removing abandoned resumable uploads: {:?}", e),
+            }
+        }
+    });
+
+    #[cfg(unix)]
+    if let Some(ref resolver) = certificate_resolver {
+        let resolver = resolver.clone();
+
+        actix_web::rt::spawn(async move {
+            let mut hangup = match signal(SignalKind::hangup()) {
+                Ok(hangup) => hangup,
+                Err(e) => {
+                    error!("Unable to listen for SIGHUP, the certificate will not be reloaded: {:?}", e);
+                    return;
+                }
+            };
+
+            while hangup.recv().await.is_some() {
+                match resolver.reload() {
+                    Ok(_) => info!("Reloaded the tls certificate"),
+                    Err(e) => error!("Error reloading the tls certificate, keeping the current one: {}", e),
+                }
+            }
+        });
+    }
+
+    let server = HttpServer::new(move || {
         App::new()
-            .data(AppData::new(application_configurations.clone())
-                .expect(format!("Error creating application properties").as_str()))
+            .app_data(app_data.clone())
+            .app_data(auth_data.clone())
             .wrap(Logger::new(&log_format))
             .wrap(Compress::default())
             .service(
                 web::resource("/")
                     .route(web::get().to(index))
-                    .route(web::post().to(save_file)),
+                    .route(web::post().to(save_file))
+                    .wrap(Condition::new(upload_requires_auth, auth_middleware.clone())),
             )
+            .service(
+                web::resource("/login")
+                    .route(web::get().to(show_login))
+                    .route(web::post().to(login)),
+            )
+            .service(web::resource("/login/oidc").route(web::get().to(start_oidc_login)))
+            .service(web::resource("/login/oidc/callback").route(web::get().to(finish_oidc_login)))
+            .service(web::resource("/logout").route(web::post().to(logout)))
             .service(
                 web::resource("files")
                     .route(web::get().to(list_files))
                     .wrap(auth_middleware.clone()),
             )
-            .route("/share/{file}", web::get().to(download_file))
+            .service(
+                web::scope("/files/{link}")
+                    .wrap(auth_middleware.clone())
+                    .route("/delete", web::post().to(delete_upload))
+                    .route("/expiry", web::post().to(set_upload_expiry))
+                    .route("/label", web::post().to(set_upload_label)),
+            )
+            .service(
+                web::resource("requests")
+                    .route(web::post().to(create_request))
+                    .wrap(auth_middleware.clone()),
+            )
+            .service(
+                web::resource("/requests/{token}/delete")
+                    .route(web::post().to(delete_request))
+                    .wrap(auth_middleware.clone()),
+            )
+            .service(
+                web::resource("tokens")
+                    .route(web::post().to(create_token))
+                    .wrap(auth_middleware.clone()),
+            )
+            .service(
+                web::resource("/tokens/{id}/revoke")
+                    .route(web::post().to(revoke_token))
+                    .wrap(auth_middleware.clone()),
+            )
+            .service(
+                web::resource("/drop/{token}")
+                    .route(web::get().to(request_page))
+                    .route(web::post().to(save_requested_file)),
+            )
+            .service(
+                web::scope(api::API_PATH)
+                    .service(
+                        web::resource("/uploads")
+                            .guard(guard::Post())
+                            .route(web::post().to(api::upload))
+                            .wrap(Condition::new(upload_requires_auth, auth_middleware.clone())),
+                    )
+                    .service(
+                        web::resource("/uploads")
+                            .route(web::get().to(api::list))
+                            .wrap(auth_middleware.clone()),
+                    )
+                    .service(
+                        web::resource("/quota")
+                            .route(web::get().to(api::quota))
+                            .wrap(auth_middleware.clone()),
+                    )
+                    .service(
+                        web::resource("/requests")
+                            .route(web::get().to(api::list_requests))
+                            .route(web::post().to(api::create_request))
+                            .wrap(auth_middleware.clone()),
+                    )
+                    .service(
+                        web::resource("/requests/{token}")
+                            .route(web::delete().to(api::delete_request))
+                            .wrap(auth_middleware.clone()),
+                    )
+                    .service(
+                        web::resource("/uploads/{link}")
+                            .route(web::get().to(api::get))
+                            .route(web::delete().to(api::delete))
+                            .wrap(auth_middleware.clone()),
+                    )
+                    .default_service(web::route().to(api::not_found)),
+            )
+            .service(
+                web::scope("/tus")
+                    .wrap(Condition::new(upload_requires_auth, auth_middleware.clone()))
+                    .wrap(DefaultHeaders::new().header("Tus-Resumable", tus::TUS_VERSION))
+                    .service(
+                        web::resource("")
+                            .route(web::method(Method::OPTIONS).to(tus::options))
+                            .route(web::post().to(tus::create)),
+                    )
+                    .service(
+                        web::resource("/{id}")
+                            .route(web::head().to(tus::head))
+                            .route(web::patch().to(tus::patch))
+                            .route(web::delete().to(tus::terminate)),
+                    ),
+            )
+            .service(
+                web::resource("/share/{file}")
+                    .route(web::get().to(download_file))
+                    .route(web::post().to(unlock_file)),
+            )
+            .service(
+                web::resource("/share/{file}/{path:.+}")
+                    .route(web::get().to(download_file))
+                    .route(web::post().to(unlock_file)),
+            )
             .service(actix_files::Files::new("/static", "static/"))
     })
-        .workers(number_of_threads)
-        .bind(addr)?
-        .run()
-        .await
+        .workers(number_of_threads);
+
+    let server = match certificate_resolver {
+        Some(resolver) => server.bind_rustls(addr, resolver.server_config())?,
+        None => server.bind(addr)?,
+    }.run();
+
+    match redirect_addr {
+        Some(redirect_addr) => {
+            let redirect_server = HttpServer::new(move || {
+                App::new()
+                    .app_data(web::Data::new(https_port))
+                    .default_service(web::route().to(tls::redirect_to_https))
+            })
+                .workers(1)
+                .bind(redirect_addr)?
+                .run();
+
+            futures::future::try_join(server, redirect_server).await.map(|_| ())
+        }
+        None => server.await,
+    }
 }
@@ -0,0 +1,91 @@
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use crate::errors::AppError;
+use crate::storage::StorageBackend;
+
+/// A file of a deduplicated upload, stored once as `blobs/<sha256>` whatever the number of uploads sharing it.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BlobFile {
+    pub name: String,
+    pub size: u64,
+    pub sha256: String,
+    pub crc32: u32,
+}
+
+/// Per upload information, kept in a `<link>.json` file next to the `<link>.zip` archive.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct UploadMetadata {
+    pub created: u64,
+    #[serde(default)]
+    pub expires_at: Option<u64>,
+    #[serde(default)]
+    pub password_hash: Option<String>,
+    #[serde(default)]
+    pub max_downloads: Option<u64>,
+    #[serde(default)]
+    pub downloads: u64,
+    #[serde(default)]
+    pub uploader: Option<String>,
+    /// Note left by the uploader for the recipients, shown on the link's page.
+    #[serde(default)]
+    pub message: Option<String>,
+    /// Name given to the upload in the admin area.
+    #[serde(default)]
+    pub label: Option<String>,
+    /// SHA-256 of the files of the archive, by name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub checksums: BTreeMap<String, String>,
+    /// SHA-256 of the whole archive, as downloaded from the link.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    /// Files of a deduplicated upload, which has no `<link>.zip` archive.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blobs: Option<Vec<BlobFile>>,
+}
+
+impl UploadMetadata {
+    pub fn key(link: &str) -> String {
+        format!("{}.json", link)
+    }
+
+    /// Reads the metadata of an upload, `None` for archives stored before metadata existed.
+    pub fn read(storage: &dyn StorageBackend, link: &str) -> Result<Option<Self>, AppError> {
+        let key = Self::key(link);
+
+        let mut content = Vec::new();
+
+        match storage.get(&key) {
+            Ok(mut reader) => reader.read_to_end(&mut content)?,
+            Err(AppError::FileNotFound) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        serde_json::from_slice(&content)
+            .map(Some)
+            .map_err(|e| AppError::FileSystemError(format!("Corrupted metadata {}: {}", key, e)))
+    }
+
+    pub fn write(&self, storage: &dyn StorageBackend, link: &str) -> Result<(), AppError> {
+        let content = serde_json::to_vec(self)
+            .map_err(|e| AppError::FileSystemError(e.to_string()))?;
+
+        storage.put(&Self::key(link), &mut content.as_slice(), content.len() as u64)
+    }
+
+    pub fn remaining_downloads(&self) -> Option<u64> {
+        self.max_downloads.map(|max| max.saturating_sub(self.downloads))
+    }
+
+    pub fn is_expired(&self) -> bool {
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        self.expires_at.map(|expires_at| expires_at <= now).unwrap_or(false)
+    }
+}
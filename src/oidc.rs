@@ -0,0 +1,353 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+use actix_web::cookie::{Cookie, SameSite};
+use base64::Engine;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+
+use crate::app_configs::OidcConfiguration;
+use crate::auth_middleware::{AuthenticatedUser, Role};
+use crate::errors::AppError;
+use crate::utils::{generate_token, percent_encode};
+
+/// Cookie tying a login sent to the provider to the browser that started it.
+pub const STATE_COOKIE: &str = "fshare_oidc";
+
+/// Path of the login routes, the only ones the state cookie is sent to.
+const STATE_COOKIE_PATH: &str = "/login/oidc";
+
+/// How long the provider has to send the user back.
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Above this many unfinished logins, new ones are refused until the old ones time out.
+const MAX_PENDING: usize = 10_000;
+
+/// Clock skew tolerated when checking the expiry of id tokens.
+const LEEWAY: u64 = 60;
+
+/// Endpoints of the provider, from its discovery document.
+#[derive(Clone, Debug, Deserialize)]
+struct Provider {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    userinfo_endpoint: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+    access_token: Option<String>,
+}
+
+#[derive(Debug)]
+struct PendingLogin {
+    verifier: String,
+    nonce: String,
+    next: Option<String>,
+    started: Instant,
+}
+
+/// Single sign-on with an OpenID Connect provider, using the authorization code flow with PKCE.
+pub struct OidcClient {
+    configs: OidcConfiguration,
+    agent: ureq::Agent,
+    provider: Mutex<Option<Provider>>,
+    pending: Mutex<HashMap<String, PendingLogin>>,
+}
+
+impl OidcClient {
+    pub fn new(configs: OidcConfiguration) -> Self {
+        if configs.issuer().starts_with("http://") {
+            warn!("The OpenID Connect issuer {} is not served over https", configs.issuer());
+        }
+
+        let agent = ureq::AgentBuilder::new()
+            .timeout(Duration::from_secs(10))
+            .build();
+
+        OidcClient { configs, agent, provider: Mutex::new(None), pending: Mutex::new(HashMap::new()) }
+    }
+
+    /// Provider name shown on the login page.
+    pub fn name(&self) -> &str {
+        self.configs.name()
+    }
+
+    /// Starts a login, answering the provider url to send the browser to and the state to keep in `STATE_COOKIE`.
+    pub fn start(&self, next: Option<&str>) -> Result<(String, String), AppError> {
+        let provider = self.provider()?;
+
+        let state = generate_token();
+        let nonce = generate_token();
+        let verifier = format!("{}{}", generate_token(), generate_token());
+        let challenge = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()));
+
+        {
+            let mut pending = self.pending.lock().map_err(|e| AppError::ThreadError(e.to_string()))?;
+
+            pending.retain(|_, login| login.started.elapsed() < LOGIN_TIMEOUT);
+
+            if pending.len() >= MAX_PENDING {
+                return Err(AppError::Forbidden("too many logins in progress".to_string()));
+            }
+
+            pending.insert(state.clone(), PendingLogin {
+                verifier,
+                nonce: nonce.clone(),
+                next: next.map(|next| next.to_string()),
+                started: Instant::now(),
+            });
+        }
+
+        let query = [
+            ("response_type", "code"),
+            ("client_id", self.configs.client_id()),
+            ("redirect_uri", self.configs.redirect_url()),
+            ("scope", self.configs.scopes()),
+            ("state", &state),
+            ("nonce", &nonce),
+            ("code_challenge", &challenge),
+            ("code_challenge_method", "S256"),
+        ];
+
+        let query = query
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, percent_encode(value)))
+            .collect::<Vec<_>>()
+            .join("&");
+
+        let separator = if provider.authorization_endpoint.contains('?') { '&' } else { '?' };
+
+        Ok((format!("{}{}{}", provider.authorization_endpoint, separator, query), state))
+    }
+
+    /// Finishes a login the provider sent back with `code`, answering the user and where it was going.
+    pub fn finish(&self, state: &str, cookie_state: Option<&str>, code: &str) -> Result<(AuthenticatedUser, Option<String>), AppError> {
+        if cookie_state != Some(state) {
+            return Err(AppError::Forbidden("state does not match the browser's".to_string()));
+        }
+
+        let login = self.pending
+            .lock()
+            .map_err(|e| AppError::ThreadError(e.to_string()))?
+            .remove(state)
+            .filter(|login| login.started.elapsed() < LOGIN_TIMEOUT)
+            .ok_or_else(|| AppError::Forbidden("unknown or expired login".to_string()))?;
+
+        let provider = self.provider()?;
+
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", self.configs.redirect_url()),
+            ("client_id", self.configs.client_id()),
+            ("code_verifier", &login.verifier),
+        ];
+
+        if let Some(secret) = self.configs.client_secret() {
+            form.push(("client_secret", secret));
+        }
+
+        let tokens: TokenResponse = self.agent
+            .post(&provider.token_endpoint)
+            .send_form(&form)
+            .map_err(provider_error)
+            .and_then(|response| response.into_string().map_err(AppError::from))
+            .and_then(|body| serde_json::from_str(&body)
+                .map_err(|e| AppError::Forbidden(format!("invalid token response: {}", e))))?;
+
+        let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?.as_secs();
+
+        let mut claims = id_token_claims(&tokens.id_token, &provider.issuer, self.configs.client_id(), &login.nonce, now)?;
+
+        // Providers may only give the user name or groups from their userinfo endpoint.
+        let missing = !claims.contains_key(self.configs.username_claim())
+            || (self.configs.uses_groups() && !claims.contains_key(self.configs.groups_claim()));
+
+        if let (true, Some(endpoint), Some(access_token)) = (missing, &provider.userinfo_endpoint, &tokens.access_token) {
+            let userinfo: Map<String, Value> = self.agent
+                .get(endpoint)
+                .set("Authorization", &format!("Bearer {}", access_token))
+                .call()
+                .map_err(provider_error)
+                .and_then(|response| response.into_string().map_err(AppError::from))
+                .and_then(|body| serde_json::from_str(&body)
+                    .map_err(|e| AppError::Forbidden(format!("invalid userinfo response: {}", e))))?;
+
+            if userinfo.get("sub") != claims.get("sub") {
+                return Err(AppError::Forbidden("userinfo is about another subject".to_string()));
+            }
+
+            for (key, value) in userinfo {
+                claims.entry(key).or_insert(value);
+            }
+        }
+
+        Ok((user(&self.configs, &claims)?, login.next))
+    }
+
+    /// The discovery document of the issuer, fetched on the first login.
+    fn provider(&self) -> Result<Provider, AppError> {
+        let mut provider = self.provider.lock().map_err(|e| AppError::ThreadError(e.to_string()))?;
+
+        if let Some(provider) = provider.as_ref() {
+            return Ok(provider.clone());
+        }
+
+        let url = format!("{}/.well-known/openid-configuration", self.configs.issuer());
+
+        let discovered: Provider = self.agent
+            .get(&url)
+            .call()
+            .map_err(provider_error)
+            .and_then(|response| response.into_string().map_err(AppError::from))
+            .and_then(|body| serde_json::from_str(&body)
+                .map_err(|e| AppError::Forbidden(format!("invalid discovery document {}: {}", url, e))))?;
+
+        if discovered.issuer.trim_end_matches('/') != self.configs.issuer() {
+            return Err(AppError::Forbidden(format!("discovery document of {} is for issuer {}", self.configs.issuer(), discovered.issuer)));
+        }
+
+        *provider = Some(discovered.clone());
+
+        Ok(discovered)
+    }
+}
+
+/// Cookie keeping the state of a login until the provider sends the browser back.
+pub fn state_cookie(state: String, secure: bool) -> Cookie<'static> {
+    Cookie::build(STATE_COOKIE, state)
+        .path(STATE_COOKIE_PATH)
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .secure(secure)
+        .max_age(time::Duration::seconds(LOGIN_TIMEOUT.as_secs() as i64))
+        .finish()
+}
+
+/// The state cookie, to be deleted from the browser with `del_cookie` once the login is over.
+pub fn state_removal_cookie() -> Cookie<'static> {
+    Cookie::build(STATE_COOKIE, "").path(STATE_COOKIE_PATH).finish()
+}
+
+fn provider_error(err: ureq::Error) -> AppError {
+    AppError::Forbidden(format!("identity provider request failed: {}", err))
+}
+
+/// Claims of an id token, once its issuer, audience, expiry and nonce are checked. Its signature is not: the token
+/// comes straight from the token endpoint, which OpenID Connect Core (3.1.3.7) lets clients trust instead.
+fn id_token_claims(id_token: &str, issuer: &str, client_id: &str, nonce: &str, now: u64) -> Result<Map<String, Value>, AppError> {
+    let invalid = |reason: &str| AppError::Forbidden(format!("invalid id token: {}", reason));
+
+    let payload = id_token.split('.').nth(1).ok_or_else(|| invalid("not a jwt"))?;
+
+    let claims: Map<String, Value> = base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(payload.trim_end_matches('='))
+        .ok()
+        .and_then(|payload| serde_json::from_slice(&payload).ok())
+        .ok_or_else(|| invalid("undecodable claims"))?;
+
+    if claims.get("iss").and_then(Value::as_str) != Some(issuer) {
+        return Err(invalid("wrong issuer"));
+    }
+
+    let audience = match claims.get("aud") {
+        Some(Value::String(audience)) => vec![audience.as_str()],
+        Some(Value::Array(audiences)) => audiences.iter().filter_map(Value::as_str).collect(),
+        _ => Vec::new(),
+    };
+
+    if !audience.contains(&client_id) {
+        return Err(invalid("wrong audience"));
+    }
+
+    if audience.len() > 1 && claims.get("azp").and_then(Value::as_str) != Some(client_id) {
+        return Err(invalid("wrong authorized party"));
+    }
+
+    if claims.get("exp").and_then(Value::as_u64).is_none_or(|exp| exp + LEEWAY <= now) {
+        return Err(invalid("expired"));
+    }
+
+    if claims.get("nonce").and_then(Value::as_str) != Some(nonce) {
+        return Err(invalid("wrong nonce"));
+    }
+
+    if claims.get("sub").and_then(Value::as_str).is_none_or(str::is_empty) {
+        return Err(invalid("no subject"));
+    }
+
+    Ok(claims)
+}
+
+/// fshare user of the claims, when one of its groups is allowed in, with the role its groups give.
+fn user(configs: &OidcConfiguration, claims: &Map<String, Value>) -> Result<AuthenticatedUser, AppError> {
+    let user = claims
+        .get(configs.username_claim())
+        .and_then(Value::as_str)
+        .map(|user| user.trim())
+        .filter(|user| !user.is_empty())
+        .ok_or_else(|| AppError::Forbidden(format!("no {} claim", configs.username_claim())))?;
+
+    let groups = match claims.get(configs.groups_claim()) {
+        Some(Value::String(group)) => vec![group.as_str()],
+        Some(Value::Array(groups)) => groups.iter().filter_map(Value::as_str).collect(),
+        _ => Vec::new(),
+    };
+
+    let in_any = |names: &[String]| groups.iter().any(|group| names.iter().any(|name| name == group));
+
+    if in_any(configs.admin_groups()) {
+        return Ok(AuthenticatedUser::new(user.to_string(), Role::Admin));
+    }
+
+    if !configs.allowed_groups().is_empty() && !in_any(configs.allowed_groups()) {
+        return Err(AppError::Forbidden(format!("{} is not in an allowed group", user)));
+    }
+
+    Ok(AuthenticatedUser::new(user.to_string(), Role::User))
+}
+
+#[test]
+fn oidc_claims_work() {
+    let configs = "[auth_oidc]\nissuer=https://idp.example.org/\nclient_id=fshare\nredirect_url=https://myshare.example/login/oidc/callback\nallowed_groups=staff\nadmin_groups=admins";
+    let configs = OidcConfiguration::try_from(&ini::Ini::load_from_str(configs).unwrap()).unwrap();
+
+    let token = |claims: Value| format!(
+        "eyJhbGciOiJSUzI1NiJ9.{}.signature",
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(claims.to_string())
+    );
+
+    let claims = serde_json::json!({
+        "iss": "https://idp.example.org", "aud": "fshare", "sub": "42", "exp": 2000, "nonce": "n",
+        "preferred_username": "alice", "groups": ["staff"]
+    });
+
+    let valid = id_token_claims(&token(claims.clone()), "https://idp.example.org", "fshare", "n", 1000).unwrap();
+
+    assert_eq!(user(&configs, &valid).unwrap(), AuthenticatedUser::new("alice".to_string(), Role::User));
+
+    assert!(id_token_claims(&token(claims.clone()), "https://idp.example.org", "fshare", "other", 1000).is_err());
+    assert!(id_token_claims(&token(claims.clone()), "https://evil.example.org", "fshare", "n", 1000).is_err());
+    assert!(id_token_claims(&token(claims.clone()), "https://idp.example.org", "other", "n", 1000).is_err());
+    assert!(id_token_claims(&token(claims.clone()), "https://idp.example.org", "fshare", "n", 3000).is_err());
+    assert!(id_token_claims("garbage", "https://idp.example.org", "fshare", "n", 1000).is_err());
+
+    let mut admin = valid.clone();
+    admin.insert("groups".to_string(), serde_json::json!(["guests", "admins"]));
+
+    assert_eq!(user(&configs, &admin).unwrap().role, Role::Admin);
+
+    let mut outsider = valid.clone();
+    outsider.insert("groups".to_string(), serde_json::json!("guests"));
+
+    assert!(user(&configs, &outsider).is_err());
+
+    outsider.remove("groups");
+
+    assert!(user(&configs, &outsider).is_err());
+}
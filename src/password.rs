@@ -0,0 +1,62 @@
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::SaltString;
+use subtle::ConstantTimeEq;
+
+use crate::errors::AppError;
+
+/// Hashes a password into a PHC string (argon2id with a random salt).
+pub fn hash_password(password: &str) -> Result<String, AppError> {
+    let salt = SaltString::generate(&mut OsRng);
+
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| AppError::InvalidRequest(format!("Unable to hash password: {}", e)))
+}
+
+/// Whether `value` looks like one of the supported hashes rather than a plaintext password.
+pub fn is_hash(value: &str) -> bool {
+    ["$argon2", "$2a$", "$2b$", "$2y$", "$5$", "$6$"]
+        .iter()
+        .any(|prefix| value.starts_with(prefix))
+}
+
+/// Checks a password against an argon2 (PHC), bcrypt or sha-crypt hash, as produced by `htpasswd` or `mkpasswd`.
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    if hash.starts_with("$argon2") {
+        PasswordHash::new(hash)
+            .map(|parsed| Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok())
+            .unwrap_or(false)
+    } else if hash.starts_with("$2") {
+        bcrypt::verify(password, hash).unwrap_or(false)
+    } else if hash.starts_with("$6$") {
+        sha_crypt::sha512_check(password, hash).is_ok()
+    } else if hash.starts_with("$5$") {
+        sha_crypt::sha256_check(password, hash).is_ok()
+    } else {
+        false
+    }
+}
+
+/// Compares plaintext passwords in constant time.
+pub fn plaintext_eq(password: &str, expected: &str) -> bool {
+    password.as_bytes().ct_eq(expected.as_bytes()).into()
+}
+
+#[test]
+fn hash_password_works() {
+    let hash = hash_password("secret").unwrap();
+
+    assert!(verify_password("secret", &hash));
+    assert!(!verify_password("Secret", &hash));
+}
+
+#[test]
+fn verify_htpasswd_hashes_works() {
+    // htpasswd -nbB root root
+    assert!(verify_password("root", "$2y$05$NjnYtO4Skeaxdl5flNKLGOhoSAy0HzjjjFnzxGm0P5s6YVPy.kRea"));
+    // mkpasswd -m sha-512 -S saltsalt root
+    assert!(verify_password("root", "$6$saltsalt$bAY90rAsHhyx.bxmKP9FE5UF4jP1iWgjV0ltM6ZJxfYkiIaCExjBZIbfmqmZEWoR65aM.1nFvG7fF3gYOjHpM."));
+    assert!(!verify_password("root", "root"));
+}
@@ -0,0 +1,219 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use actix_web::cookie::{Cookie, SameSite};
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::app_configs::SessionConfiguration;
+use crate::auth_middleware::{AuthenticatedUser, Role};
+use crate::utils::generate_token;
+
+/// Cookie holding the session of a user logged in with the login form.
+pub const SESSION_COOKIE: &str = "fshare_session";
+
+/// Cookie remembering that the password of a link was given, only sent back for its urls.
+pub const UNLOCK_COOKIE: &str = "fshare_unlock";
+
+/// How long a link stays unlocked once its password was given.
+const UNLOCK_LIFETIME: Duration = Duration::from_secs(3600);
+
+/// Sessions kept in signed cookies as `<id>.<user>.<expires>.<role>.<signature>`, nothing is stored for them
+/// but the ids of those ended by a logout.
+#[derive(Debug)]
+pub struct SessionManager {
+    key: Vec<u8>,
+    lifetime: Duration,
+    /// Logged out session ids, until they expire anyway.
+    revoked: Mutex<HashMap<String, u64>>,
+}
+
+impl SessionManager {
+    pub fn new(configs: &SessionConfiguration) -> Self {
+        let key = match configs.secret() {
+            Some(secret) => secret.as_bytes().to_vec(),
+            None => {
+                warn!("No session secret configured, sessions will end when the server restarts");
+
+                generate_token().into_bytes()
+            }
+        };
+
+        SessionManager { key, lifetime: configs.lifetime(), revoked: Mutex::new(HashMap::new()) }
+    }
+
+    /// Starts a session for `user`, as a cookie to send back. `role` is the one granted at login, by the identity
+    /// provider; the `admins` of the configuration are admins whatever their session says.
+    pub fn create(&self, user: &str, role: Role, secure: bool) -> Cookie<'static> {
+        let expires = now() + self.lifetime.as_secs();
+
+        let role = match role {
+            Role::User => "user",
+            Role::Admin => "admin",
+        };
+
+        let payload = format!("{}.{}.{}.{}", generate_token(), encode(user.as_bytes()), expires, role);
+        let value = format!("{}.{}", payload, encode(&self.sign(&payload)));
+
+        Cookie::build(SESSION_COOKIE, value)
+            .path("/")
+            .http_only(true)
+            .same_site(SameSite::Lax)
+            .secure(secure)
+            .max_age(time::Duration::seconds(self.lifetime.as_secs() as i64))
+            .finish()
+    }
+
+    /// User of a session cookie, `None` when it is forged, expired or logged out.
+    pub fn user(&self, value: &str) -> Option<AuthenticatedUser> {
+        self.user_at(value, now())
+    }
+
+    /// Ends a session before its expiry.
+    pub fn revoke(&self, value: &str) {
+        let now = now();
+
+        if let (Some((id, _, expires, _)), Ok(mut revoked)) = (self.verify(value), self.revoked.lock()) {
+            revoked.retain(|_, expires| *expires > now);
+            revoked.insert(id, expires);
+        }
+    }
+
+    /// Remembers that the password of `link` was given, as a cookie to send back.
+    pub fn unlock(&self, link: &str, secure: bool) -> Cookie<'static> {
+        let expires = now() + UNLOCK_LIFETIME.as_secs();
+
+        Cookie::build(UNLOCK_COOKIE, format!("{}.{}", expires, encode(&self.sign_unlock(link, expires))))
+            .path(format!("/share/{}", link))
+            .http_only(true)
+            .same_site(SameSite::Lax)
+            .secure(secure)
+            .max_age(time::Duration::seconds(UNLOCK_LIFETIME.as_secs() as i64))
+            .finish()
+    }
+
+    /// Whether an unlock cookie was given for `link` and is still valid.
+    pub fn is_unlocked(&self, value: &str, link: &str) -> bool {
+        self.is_unlocked_at(value, link, now())
+    }
+
+    fn is_unlocked_at(&self, value: &str, link: &str, now: u64) -> bool {
+        let (expires, signature) = match value.split_once('.').and_then(|(expires, signature)| Some((expires.parse::<u64>().ok()?, decode(signature)?))) {
+            Some(cookie) => cookie,
+            None => return false,
+        };
+
+        expires > now && self.unlock_mac(link, expires).verify_slice(&signature).is_ok()
+    }
+
+    /// The session cookie, to be deleted from the browser with `del_cookie`.
+    pub fn removal_cookie() -> Cookie<'static> {
+        Cookie::build(SESSION_COOKIE, "").path("/").finish()
+    }
+
+    fn user_at(&self, value: &str, now: u64) -> Option<AuthenticatedUser> {
+        let (id, user, expires, role) = self.verify(value)?;
+
+        if expires <= now || self.revoked.lock().ok()?.contains_key(&id) {
+            return None;
+        }
+
+        Some(AuthenticatedUser::new(user, role))
+    }
+
+    /// Id, user, expiry and role of a cookie signed with our key.
+    fn verify(&self, value: &str) -> Option<(String, String, u64, Role)> {
+        let (payload, signature) = value.rsplit_once('.')?;
+
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).ok()?;
+        mac.update(payload.as_bytes());
+        mac.verify_slice(&decode(signature)?).ok()?;
+
+        let mut parts = payload.splitn(4, '.');
+
+        let id = parts.next()?.to_string();
+        let user = String::from_utf8(decode(parts.next()?)?).ok()?;
+        let expires = parts.next()?.parse::<u64>().ok()?;
+
+        // Sessions started before roles existed have none.
+        let role = match parts.next() {
+            Some("admin") => Role::Admin,
+            _ => Role::User,
+        };
+
+        Some((id, user, expires, role))
+    }
+
+    fn sign(&self, payload: &str) -> Vec<u8> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).expect("hmac accepts keys of any size");
+        mac.update(payload.as_bytes());
+        mac.finalize().into_bytes().to_vec()
+    }
+
+    fn sign_unlock(&self, link: &str, expires: u64) -> Vec<u8> {
+        self.unlock_mac(link, expires).finalize().into_bytes().to_vec()
+    }
+
+    /// Unlock cookies are signed with a key of their own, so that they are never valid sessions.
+    fn unlock_mac(&self, link: &str, expires: u64) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.sign("unlock")).expect("hmac accepts keys of any size");
+        mac.update(format!("{}.{}", link, expires).as_bytes());
+        mac
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn encode(bytes: &[u8]) -> String {
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+}
+
+fn decode(value: &str) -> Option<Vec<u8>> {
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.decode(value).ok()
+}
+
+#[test]
+fn sessions_work() {
+    let configs = ini::Ini::load_from_str("[session]\nsecret=not so secret\nlifetime=1h").unwrap();
+    let sessions = SessionManager::new(&SessionConfiguration::try_from(&configs).unwrap());
+
+    let cookie = sessions.create("alice.smith", Role::User, false);
+
+    assert_eq!(sessions.user(cookie.value()), Some(AuthenticatedUser::new("alice.smith".to_string(), Role::User)));
+    assert_eq!(sessions.user_at(cookie.value(), now() + 3600), None);
+
+    let forged = cookie.value().replacen(&encode(b"alice.smith"), &encode(b"root"), 1);
+
+    assert_eq!(sessions.user(&forged), None);
+    assert_eq!(sessions.user("garbage"), None);
+
+    let other = SessionManager::new(&SessionConfiguration::try_from(&ini::Ini::new()).unwrap());
+
+    assert_eq!(other.user(cookie.value()), None);
+
+    sessions.revoke(cookie.value());
+
+    assert_eq!(sessions.user(cookie.value()), None);
+    assert_eq!(sessions.user(sessions.create("alice.smith", Role::User, false).value()).map(|user| user.name), Some("alice.smith".to_string()));
+
+    let admin = sessions.create("root", Role::Admin, false);
+
+    assert_eq!(sessions.user(admin.value()).map(|user| user.role), Some(Role::Admin));
+    assert_eq!(sessions.user(&cookie.value().replacen(".user.", ".admin.", 1)), None);
+
+    let unlock = sessions.unlock("abcdefghij", false);
+
+    assert_eq!(unlock.path(), Some("/share/abcdefghij"));
+    assert!(sessions.is_unlocked(unlock.value(), "abcdefghij"));
+    assert!(!sessions.is_unlocked(unlock.value(), "klmnopqrst"));
+    assert!(!sessions.is_unlocked_at(unlock.value(), "abcdefghij", now() + 3600));
+    assert!(!other.is_unlocked(unlock.value(), "abcdefghij"));
+    assert_eq!(sessions.user(&format!("unlock.abcdefghij.{}", unlock.value())), None);
+}
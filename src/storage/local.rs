@@ -0,0 +1,107 @@
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use crate::errors::AppError;
+use crate::storage::{ObjectInfo, SeekableRead, StorageBackend};
+
+/// Objects kept as files of a local directory, the `upload_directory`.
+#[derive(Clone, Debug)]
+pub struct LocalStorage {
+    directory: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(directory: PathBuf) -> Self {
+        LocalStorage { directory }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.directory.join(key)
+    }
+
+    /// Path of `key` to write to, creating its directory for keys such as `blobs/<sha256>`.
+    fn write_path(&self, key: &str) -> Result<PathBuf, AppError> {
+        let path = self.path(key);
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        Ok(path)
+    }
+}
+
+impl StorageBackend for LocalStorage {
+    fn put(&self, key: &str, source: &mut dyn Read, _length: u64) -> Result<(), AppError> {
+        std::io::copy(source, &mut std::fs::File::create(self.write_path(key)?)?)?;
+
+        Ok(())
+    }
+
+    fn put_file(&self, key: &str, source: &Path) -> Result<(), AppError> {
+        // Staged files usually live on the same filesystem, so this is just a rename.
+        let path = self.write_path(key)?;
+
+        if std::fs::rename(source, &path).is_ok() {
+            return Ok(());
+        }
+
+        std::fs::copy(source, path)?;
+        std::fs::remove_file(source)?;
+
+        Ok(())
+    }
+
+    fn get(&self, key: &str) -> Result<Box<dyn Read + Send>, AppError> {
+        Ok(Box::new(self.open(key)?))
+    }
+
+    fn get_range(&self, key: &str, offset: u64, length: u64) -> Result<Box<dyn Read + Send>, AppError> {
+        let mut file = self.open(key)?;
+
+        file.seek(SeekFrom::Start(offset))?;
+
+        Ok(Box::new(file.take(length)))
+    }
+
+    fn open(&self, key: &str) -> Result<Box<dyn SeekableRead>, AppError> {
+        match std::fs::File::open(self.path(key)) {
+            Ok(file) => Ok(Box::new(file)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Err(AppError::FileNotFound),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn list(&self) -> Result<Vec<String>, AppError> {
+        let mut keys = Vec::new();
+
+        for entry in std::fs::read_dir(&self.directory)? {
+            let entry = entry?;
+
+            if entry.file_type()?.is_file() {
+                keys.push(entry.file_name().to_string_lossy().to_string());
+            }
+        }
+
+        Ok(keys)
+    }
+
+    fn delete(&self, key: &str) -> Result<(), AppError> {
+        match std::fs::remove_file(self.path(key)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn stat(&self, key: &str) -> Result<Option<ObjectInfo>, AppError> {
+        match self.path(key).metadata() {
+            Ok(metadata) => Ok(Some(ObjectInfo { size: metadata.len(), modified: metadata.modified()? })),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn local_path(&self, key: &str) -> Option<PathBuf> {
+        Some(self.path(key))
+    }
+}
@@ -0,0 +1,139 @@
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use crate::app_configs::{StorageStrategy, UploadConfiguration};
+use crate::errors::AppError;
+pub use crate::storage::local::LocalStorage;
+pub use crate::storage::s3::S3Storage;
+
+mod local;
+mod s3;
+
+/// Size and modification time of a stored object.
+#[derive(Clone, Debug)]
+pub struct ObjectInfo {
+    pub size: u64,
+    pub modified: SystemTime,
+}
+
+/// Readers that can also seek, as needed to read zip archives.
+pub trait SeekableRead: Read + Seek + Send {}
+
+impl<T: Read + Seek + Send> SeekableRead for T {}
+
+/// Where archives and their metadata files are kept, by key (`<link>.zip`, `<link>.json`).
+pub trait StorageBackend: std::fmt::Debug + Send + Sync {
+    /// Stores `length` bytes read from `source` under `key`, replacing any previous object.
+    fn put(&self, key: &str, source: &mut dyn Read, length: u64) -> Result<(), AppError>;
+
+    /// Stores a local file under `key`, consuming it.
+    fn put_file(&self, key: &str, source: &Path) -> Result<(), AppError> {
+        let length = source.metadata()?.len();
+
+        self.put(key, &mut std::fs::File::open(source)?, length)?;
+
+        std::fs::remove_file(source)?;
+
+        Ok(())
+    }
+
+    fn get(&self, key: &str) -> Result<Box<dyn Read + Send>, AppError>;
+
+    /// Reads `length` bytes of `key`, starting at `offset`.
+    fn get_range(&self, key: &str, offset: u64, length: u64) -> Result<Box<dyn Read + Send>, AppError>;
+
+    /// Opens `key` for random access, e.g. to read a single file of an archive.
+    fn open(&self, key: &str) -> Result<Box<dyn SeekableRead>, AppError>;
+
+    /// Keys of every stored object.
+    fn list(&self) -> Result<Vec<String>, AppError>;
+
+    /// Deletes `key`, succeeding when it does not exist.
+    fn delete(&self, key: &str) -> Result<(), AppError>;
+
+    fn stat(&self, key: &str) -> Result<Option<ObjectInfo>, AppError>;
+
+    /// Path of `key` on the local disk, when it can be served from there directly.
+    fn local_path(&self, _key: &str) -> Option<PathBuf> {
+        None
+    }
+}
+
+pub fn get_storage(configs: &UploadConfiguration) -> Result<Arc<dyn StorageBackend>, AppError> {
+    match configs.storage() {
+        StorageStrategy::Local => Ok(Arc::new(LocalStorage::new(configs.upload_directory().clone()))),
+        StorageStrategy::S3(ref s3) => Ok(Arc::new(S3Storage::new(s3.clone())?)),
+    }
+}
+
+/// Size of the blocks fetched by a `RangeReader`.
+const RANGE_BLOCK_SIZE: u64 = 1024 * 1024;
+
+/// Seekable reader over an object, fetching it in blocks with ranged reads.
+pub struct RangeReader<S: StorageBackend> {
+    storage: S,
+    key: String,
+    size: u64,
+    position: u64,
+    block_start: u64,
+    block: Vec<u8>,
+}
+
+impl<S: StorageBackend> RangeReader<S> {
+    pub fn new(storage: S, key: &str, size: u64) -> Self {
+        RangeReader { storage, key: key.to_string(), size, position: 0, block_start: 0, block: Vec::new() }
+    }
+}
+
+impl<S: StorageBackend> Read for RangeReader<S> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.position >= self.size || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let block_end = self.block_start + self.block.len() as u64;
+
+        if self.position < self.block_start || self.position >= block_end {
+            let length = RANGE_BLOCK_SIZE.min(self.size - self.position);
+
+            let mut block = Vec::with_capacity(length as usize);
+
+            self.storage
+                .get_range(&self.key, self.position, length)
+                .map_err(|e| std::io::Error::other(e.to_string()))?
+                .read_to_end(&mut block)?;
+
+            if block.is_empty() {
+                return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "empty range"));
+            }
+
+            self.block_start = self.position;
+            self.block = block;
+        }
+
+        let offset = (self.position - self.block_start) as usize;
+        let read = buf.len().min(self.block.len() - offset);
+
+        buf[..read].copy_from_slice(&self.block[offset..offset + read]);
+        self.position += read as u64;
+
+        Ok(read)
+    }
+}
+
+impl<S: StorageBackend> Seek for RangeReader<S> {
+    fn seek(&mut self, position: SeekFrom) -> std::io::Result<u64> {
+        let position = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.size.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+
+        self.position = position
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "seek before the start"))?;
+
+        Ok(self.position)
+    }
+}
@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::app_configs::ThrottleConfiguration;
+
+/// Above this many tracked clients, those without a recent failure are forgotten.
+const MAX_TRACKED: usize = 10_000;
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+enum Client {
+    Address(IpAddr),
+    User(String),
+}
+
+#[derive(Debug)]
+struct Failures {
+    count: u32,
+    last: Instant,
+    locked_until: Option<Instant>,
+}
+
+/// Failed logins by address and by user name, locking both out for longer after every failure.
+#[derive(Debug)]
+pub struct LoginThrottle {
+    configs: ThrottleConfiguration,
+    failures: Mutex<HashMap<Client, Failures>>,
+}
+
+impl LoginThrottle {
+    pub fn new(configs: ThrottleConfiguration) -> Self {
+        LoginThrottle { configs, failures: Mutex::new(HashMap::new()) }
+    }
+
+    /// How long `user` logging in from `address` must wait, `None` when it may try now.
+    pub fn retry_after(&self, address: Option<IpAddr>, user: &str) -> Option<Duration> {
+        self.retry_after_at(Instant::now(), address, user)
+    }
+
+    pub fn failure(&self, address: Option<IpAddr>, user: &str) {
+        self.failure_at(Instant::now(), address, user)
+    }
+
+    pub fn success(&self, address: Option<IpAddr>, user: &str) {
+        if let Ok(mut failures) = self.failures.lock() {
+            for client in self.clients(address, user) {
+                failures.remove(&client);
+            }
+        }
+    }
+
+    fn retry_after_at(&self, now: Instant, address: Option<IpAddr>, user: &str) -> Option<Duration> {
+        let failures = self.failures.lock().ok()?;
+
+        self.clients(address, user)
+            .iter()
+            .filter_map(|client| failures.get(client))
+            .filter_map(|failures| failures.locked_until)
+            .filter(|locked_until| *locked_until > now)
+            .max()
+            .map(|locked_until| locked_until - now)
+    }
+
+    fn failure_at(&self, now: Instant, address: Option<IpAddr>, user: &str) {
+        let mut failures = match self.failures.lock() {
+            Ok(failures) => failures,
+            Err(_) => return,
+        };
+
+        if failures.len() >= MAX_TRACKED {
+            let reset_after = self.configs.reset_after();
+
+            failures.retain(|_, failures| now.duration_since(failures.last) < reset_after);
+        }
+
+        for client in self.clients(address, user) {
+            if failures.len() >= MAX_TRACKED && !failures.contains_key(&client) {
+                continue;
+            }
+
+            let entry = failures.entry(client.clone()).or_insert(Failures { count: 0, last: now, locked_until: None });
+
+            if now.duration_since(entry.last) >= self.configs.reset_after() {
+                entry.count = 0;
+            }
+
+            entry.count += 1;
+            entry.last = now;
+
+            if let Some(lockout) = self.lockout(entry.count) {
+                warn!("Locking out {:?} for {:?} after {} failed logins", client, lockout, entry.count);
+
+                entry.locked_until = Some(now + lockout);
+            }
+        }
+    }
+
+    /// Lockout after the `count`th failure in a row.
+    fn lockout(&self, count: u32) -> Option<Duration> {
+        let over = count.checked_sub(self.configs.free_attempts()).filter(|over| *over > 0)?;
+
+        let lockout = self.configs.base_lockout()
+            .checked_mul(2u32.saturating_pow(over - 1))
+            .unwrap_or(Duration::MAX);
+
+        Some(lockout.min(self.configs.max_lockout()))
+    }
+
+    /// What a login is tracked by, nothing for trusted networks.
+    fn clients(&self, address: Option<IpAddr>, user: &str) -> Vec<Client> {
+        if !self.configs.enabled() {
+            return Vec::new();
+        }
+
+        if let Some(address) = address {
+            if self.configs.trusted_networks().iter().any(|network| network.contains(&address)) {
+                return Vec::new();
+            }
+        }
+
+        address.map(Client::Address).into_iter().chain(Some(Client::User(user.to_string()))).collect()
+    }
+}
+
+#[test]
+fn throttle_works() {
+    let configs = "[auth_throttle]\nfree_attempts=2\nbase_lockout=10s\nmax_lockout=30s\ntrusted_networks=10.0.0.0/8";
+    let configs = ini::Ini::load_from_str(configs).unwrap();
+
+    let throttle = LoginThrottle::new(ThrottleConfiguration::try_from(&configs).unwrap());
+
+    let start = Instant::now();
+    let address = Some("192.168.1.10".parse().unwrap());
+
+    throttle.failure_at(start, address, "alice");
+    throttle.failure_at(start, address, "alice");
+
+    assert_eq!(throttle.retry_after_at(start, address, "alice"), None);
+
+    throttle.failure_at(start, address, "alice");
+
+    assert_eq!(throttle.retry_after_at(start, address, "alice"), Some(Duration::from_secs(10)));
+    assert_eq!(throttle.retry_after_at(start, None, "alice"), Some(Duration::from_secs(10)));
+    assert_eq!(throttle.retry_after_at(start, address, "bob"), Some(Duration::from_secs(10)));
+    assert_eq!(throttle.retry_after_at(start + Duration::from_secs(10), address, "alice"), None);
+
+    throttle.failure_at(start, address, "alice");
+    throttle.failure_at(start, address, "alice");
+
+    assert_eq!(throttle.retry_after_at(start, address, "alice"), Some(Duration::from_secs(30)));
+
+    for _ in 0..5 {
+        throttle.failure_at(start, Some("10.1.2.3".parse().unwrap()), "carol");
+    }
+
+    assert_eq!(throttle.retry_after_at(start, Some("10.1.2.3".parse().unwrap()), "carol"), None);
+
+    throttle.success(address, "alice");
+
+    assert_eq!(throttle.retry_after_at(start, address, "alice"), None);
+}
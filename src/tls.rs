@@ -0,0 +1,101 @@
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use actix_web::{HttpRequest, HttpResponse, web};
+use rustls::internal::pemfile::{certs, pkcs8_private_keys, rsa_private_keys};
+use rustls::sign::{any_supported_type, CertifiedKey};
+use rustls::{ClientHello, NoClientAuth, ResolvesServerCert, ServerConfig};
+
+use crate::errors::AppError;
+
+/// Serves the certificate chain and private key read from PEM files, which can be swapped while running.
+pub struct CertificateResolver {
+    certificate: PathBuf,
+    private_key: PathBuf,
+    current: RwLock<CertifiedKey>,
+}
+
+impl CertificateResolver {
+    pub fn new(certificate: &Path, private_key: &Path) -> Result<Self, AppError> {
+        Ok(CertificateResolver {
+            current: RwLock::new(Self::load(certificate, private_key)?),
+            certificate: certificate.to_path_buf(),
+            private_key: private_key.to_path_buf(),
+        })
+    }
+
+    /// Reads the files again, keeping the current certificate when they are invalid.
+    ///
+    /// Established connections are not affected, only new handshakes use the new certificate.
+    pub fn reload(&self) -> Result<(), AppError> {
+        let key = Self::load(&self.certificate, &self.private_key)?;
+
+        *self.current
+            .write()
+            .map_err(|_| AppError::ThreadError("certificate lock poisoned".to_string()))? = key;
+
+        Ok(())
+    }
+
+    pub fn server_config(self: &Arc<Self>) -> ServerConfig {
+        let mut config = ServerConfig::new(NoClientAuth::new());
+
+        config.cert_resolver = self.clone();
+
+        config
+    }
+
+    fn load(certificate: &Path, private_key: &Path) -> Result<CertifiedKey, AppError> {
+        let open = |path: &Path| std::fs::File::open(path)
+            .map(BufReader::new)
+            .map_err(|e| AppError::InitError(format!("Unable to read {}: {}", path.display(), e)));
+
+        let chain = certs(&mut open(certificate)?)
+            .ok()
+            .filter(|chain| !chain.is_empty())
+            .ok_or_else(|| AppError::InitError(format!("No PEM certificate in {}", certificate.display())))?;
+
+        // PKCS#8 ("BEGIN PRIVATE KEY") first, then PKCS#1 ("BEGIN RSA PRIVATE KEY").
+        let key = pkcs8_private_keys(&mut open(private_key)?)
+            .ok()
+            .and_then(|mut keys| keys.pop())
+            .or_else(|| open(private_key).ok()
+                .and_then(|mut reader| rsa_private_keys(&mut reader).ok())
+                .and_then(|mut keys| keys.pop()))
+            .ok_or_else(|| AppError::InitError(format!("No PEM private key in {}", private_key.display())))?;
+
+        let signing_key = any_supported_type(&key)
+            .map_err(|_| AppError::InitError(format!("Unsupported private key in {}", private_key.display())))?;
+
+        Ok(CertifiedKey::new(chain, Arc::new(signing_key)))
+    }
+}
+
+impl ResolvesServerCert for CertificateResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<CertifiedKey> {
+        self.current.read().ok().map(|key| key.clone())
+    }
+}
+
+/// Handler of the plain http listener, sending every request to the same url over https.
+pub async fn redirect_to_https(req: HttpRequest, https_port: web::Data<u16>) -> HttpResponse {
+    let connection_info = req.connection_info();
+
+    let host = connection_info.host();
+
+    // Drops the port of `host:port` and `[::1]:port`, but not the colons of a bare ipv6 address.
+    let host = match host.rfind(':') {
+        Some(index) if !host[index..].contains(']') => &host[..index],
+        _ => host,
+    };
+
+    let path = req.uri().path_and_query().map(|path| path.as_str()).unwrap_or("/");
+
+    let location = match **https_port {
+        443 => format!("https://{}{}", host, path),
+        port => format!("https://{}:{}{}", host, port, path),
+    };
+
+    HttpResponse::MovedPermanently().header("Location", location).finish()
+}
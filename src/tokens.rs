@@ -0,0 +1,130 @@
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use sha2::{Digest, Sha256};
+
+use crate::errors::AppError;
+use crate::index::{ApiToken, UploadIndex};
+use crate::password::plaintext_eq;
+use crate::utils::{generate_random_link, generate_token, is_link_valid};
+
+/// Start of every token, telling them apart from passwords (and making leaked ones easy to search for).
+const TOKEN_PREFIX: &str = "fst";
+
+const MAX_NAME_LENGTH: usize = 100;
+
+/// Personal bearer tokens, `fst_<id>_<secret>`, of which only the SHA-256 of the secret is stored.
+#[derive(Debug)]
+pub struct ApiTokens {
+    index: Arc<UploadIndex>,
+}
+
+impl ApiTokens {
+    pub fn new(index: Arc<UploadIndex>) -> Self {
+        ApiTokens { index }
+    }
+
+    /// Creates a token for `user`, returning it with the secret value, which is not kept anywhere.
+    pub fn create(&self, user: &str, name: &str, expires_in: Option<Duration>) -> Result<(ApiToken, String), AppError> {
+        let name = name.trim();
+
+        if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+            return Err(AppError::InvalidRequest(format!("Token names must have 1 to {} characters", MAX_NAME_LENGTH)));
+        }
+
+        let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?;
+
+        let token = ApiToken {
+            id: generate_random_link(),
+            user: user.to_string(),
+            name: name.to_string(),
+            created: now.as_secs(),
+            expires_at: expires_in.map(|expires_in| (now + expires_in).as_secs()),
+            last_used: None,
+        };
+
+        let secret = generate_token();
+
+        self.index.insert_token(&token, &hash(&secret))?;
+
+        let value = format!("{}_{}_{}", TOKEN_PREFIX, token.id, secret);
+
+        Ok((token, value))
+    }
+
+    /// User of a token, `None` when unknown, expired or revoked.
+    pub fn authenticate(&self, value: &str) -> Result<Option<String>, AppError> {
+        let (id, secret) = match parse(value) {
+            Some(parts) => parts,
+            None => return Ok(None),
+        };
+
+        let (token, expected) = match self.index.get_token(id)? {
+            Some(token) => token,
+            None => return Ok(None),
+        };
+
+        let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?.as_secs();
+
+        if !plaintext_eq(&hash(secret), &expected) || token.expires_at.is_some_and(|expires_at| expires_at <= now) {
+            return Ok(None);
+        }
+
+        self.index.touch_token(id, now)?;
+
+        Ok(Some(token.user))
+    }
+
+    pub fn list(&self, user: &str) -> Result<Vec<ApiToken>, AppError> {
+        self.index.list_tokens(user)
+    }
+
+    pub fn revoke(&self, user: &str, id: &str) -> Result<(), AppError> {
+        if !is_link_valid(id) || !self.index.remove_token(id, user)? {
+            return Err(AppError::FileNotFound);
+        }
+
+        Ok(())
+    }
+}
+
+/// Id of a token, e.g. to throttle failed attempts with it.
+pub fn token_id(value: &str) -> Option<&str> {
+    parse(value).map(|(id, _)| id)
+}
+
+fn parse(value: &str) -> Option<(&str, &str)> {
+    let mut parts = value.trim().splitn(3, '_');
+
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(TOKEN_PREFIX), Some(id), Some(secret)) if is_link_valid(id) && !id.is_empty() => Some((id, secret)),
+        _ => None,
+    }
+}
+
+fn hash(secret: &str) -> String {
+    format!("{:x}", Sha256::digest(secret.as_bytes()))
+}
+
+#[test]
+fn tokens_work() {
+    let (index, _) = UploadIndex::open(std::path::Path::new(":memory:"), std::path::Path::new(":memory:")).unwrap();
+    let tokens = ApiTokens::new(Arc::new(index));
+
+    let (token, value) = tokens.create("alice", "ci", None).unwrap();
+
+    assert!(value.starts_with("fst_"));
+    assert_eq!(tokens.authenticate(&value).unwrap(), Some("alice".to_string()));
+    assert!(tokens.list("alice").unwrap()[0].last_used.is_some());
+    assert_eq!(tokens.authenticate(&format!("{}x", value)).unwrap(), None);
+    assert_eq!(tokens.authenticate("fst_nope_secret").unwrap(), None);
+    assert_eq!(tokens.authenticate("password").unwrap(), None);
+    assert!(tokens.create("alice", " ", None).is_err());
+
+    assert!(tokens.revoke("bob", &token.id).is_err());
+
+    tokens.revoke("alice", &token.id).unwrap();
+
+    assert_eq!(tokens.authenticate(&value).unwrap(), None);
+    assert!(tokens.list("alice").unwrap().is_empty());
+}
@@ -0,0 +1,547 @@
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use actix_web::{HttpRequest, HttpResponse, web};
+use actix_web::http::header::HttpDate;
+use actix_web::web::Buf;
+use base64::Engine;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+
+use crate::app::AppData;
+use crate::auth_middleware::AuthenticatedUser;
+use crate::errors::AppError;
+use crate::upload::{QuotaReservation, UploadOptions};
+use crate::utils::{generate_random_link, is_link_valid};
+
+pub const TUS_VERSION: &str = "1.0.0";
+
+const TUS_EXTENSIONS: &str = "creation,termination,expiration";
+
+const OFFSET_CONTENT_TYPE: &str = "application/offset+octet-stream";
+
+/// State of a resumable upload, persisted next to its data so it survives restarts.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TusUpload {
+    length: u64,
+    metadata: HashMap<String, String>,
+    created: u64,
+    #[serde(default)]
+    uploader: Option<String>,
+}
+
+impl TusUpload {
+    /// Upload settings sent by the client as metadata, e.g. `expires_in`.
+    pub fn options(&self) -> Result<UploadOptions, AppError> {
+        Ok(UploadOptions::from_fields(&self.metadata)?.with_uploader(self.uploader.clone()))
+    }
+
+    /// Uploads created by an authenticated user can only be accessed by that user.
+    fn check_uploader(&self, user: Option<&str>) -> Result<(), AppError> {
+        match self.uploader {
+            Some(ref uploader) if user != Some(uploader.as_str()) => {
+                Err(AppError::FileNotFound)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Name of the file inside the final archive, taken from the client metadata.
+    pub fn filename(&self) -> Option<String> {
+        self.metadata
+            .get("filename")
+            .or_else(|| self.metadata.get("name"))
+            .map(sanitize_filename::sanitize)
+            .filter(|name| !name.is_empty())
+    }
+}
+
+pub struct TusManager {
+    staging: PathBuf,
+    max_size: usize,
+    expiry: Option<Duration>,
+    in_progress: Mutex<HashSet<String>>,
+    /// Quota held by unfinished uploads for their whole length.
+    reservations: Mutex<HashMap<String, QuotaReservation>>,
+}
+
+/// Releases the per-upload lock taken by `TusManager::lock` when dropped.
+pub struct TusLock<'a> {
+    manager: &'a TusManager,
+    id: String,
+}
+
+impl Drop for TusLock<'_> {
+    fn drop(&mut self) {
+        if let Ok(mut in_progress) = self.manager.in_progress.lock() {
+            in_progress.remove(&self.id);
+        }
+    }
+}
+
+impl TusManager {
+    pub fn new(staging: PathBuf, max_size: usize, expiry: Option<Duration>) -> Result<Self, AppError> {
+        if !staging.exists() {
+            std::fs::create_dir_all(&staging).map_err(|e| {
+                AppError::InitError(format!("Error creating tus staging directory: {}", e))
+            })?;
+        }
+
+        Ok(TusManager {
+            staging,
+            max_size,
+            expiry,
+            in_progress: Mutex::new(HashSet::new()),
+            reservations: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    fn info_path(&self, id: &str) -> PathBuf {
+        self.staging.join(format!("{}.info", id))
+    }
+
+    pub fn data_path(&self, id: &str) -> PathBuf {
+        self.staging.join(format!("{}.bin", id))
+    }
+
+    /// Time of the last chunk received.
+    fn last_write(&self, id: &str) -> Result<SystemTime, AppError> {
+        let info = self.info_path(id).metadata()?.modified()?;
+
+        Ok(match self.data_path(id).metadata().and_then(|metadata| metadata.modified()) {
+            Ok(data) => info.max(data),
+            Err(_) => info,
+        })
+    }
+
+    /// When `id` will be deleted unless more data arrives, `None` when uploads never expire.
+    pub fn expires(&self, id: &str) -> Result<Option<SystemTime>, AppError> {
+        match self.expiry {
+            Some(expiry) => Ok(Some(self.last_write(id)? + expiry)),
+            None => Ok(None),
+        }
+    }
+
+    fn write_info(&self, id: &str, upload: &TusUpload) -> Result<(), AppError> {
+        let content = serde_json::to_vec(upload)
+            .map_err(|e| AppError::FileSystemError(e.to_string()))?;
+
+        std::fs::write(self.info_path(id), content)?;
+
+        Ok(())
+    }
+
+    pub fn create(&self, length: u64, metadata: HashMap<String, String>, uploader: Option<String>) -> Result<String, AppError> {
+        if length > self.max_size as u64 {
+            return Err(AppError::PayloadTooLarge(format!("Uploads are limited to {} bytes", self.max_size)));
+        }
+
+        let id = generate_random_link();
+
+        let upload = TusUpload {
+            length,
+            metadata,
+            created: SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?.as_secs(),
+            uploader,
+        };
+
+        // Keeps the cleanup away from the files until both exist.
+        let _lock = self.lock(&id)?;
+
+        std::fs::File::create(self.data_path(&id))?;
+        self.write_info(&id, &upload)?;
+
+        Ok(id)
+    }
+
+    /// Returns the upload state and the number of bytes received so far.
+    pub fn get(&self, id: &str) -> Result<(TusUpload, u64), AppError> {
+        if !is_link_valid(id) {
+            return Err(AppError::FileNotFound);
+        }
+
+        let content = std::fs::read(self.info_path(id)).map_err(|_| AppError::FileNotFound)?;
+
+        if self.expires(id)?.is_some_and(|expires| expires <= SystemTime::now()) {
+            return Err(AppError::FileNotFound);
+        }
+
+        let upload: TusUpload = serde_json::from_slice(&content)
+            .map_err(|e| AppError::FileSystemError(format!("Corrupted tus upload {}: {}", id, e)))?;
+
+        let offset = self.data_path(id).metadata().map_err(|_| AppError::FileNotFound)?.len();
+
+        Ok((upload, offset))
+    }
+
+    /// Whether the quota of `id` is reserved; reservations are not kept across restarts.
+    pub fn is_reserved(&self, id: &str) -> bool {
+        self.reservations.lock().is_ok_and(|reservations| reservations.contains_key(id))
+    }
+
+    /// Holds `reservation` until `id` is finished, terminated or expired.
+    pub fn reserve(&self, id: &str, reservation: Option<QuotaReservation>) {
+        if let (Some(reservation), Ok(mut reservations)) = (reservation, self.reservations.lock()) {
+            reservations.insert(id.to_string(), reservation);
+        }
+    }
+
+    fn release(&self, id: &str) {
+        if let Ok(mut reservations) = self.reservations.lock() {
+            reservations.remove(id);
+        }
+    }
+
+    /// Prevents two requests from writing the same upload at the same time.
+    pub fn lock(&self, id: &str) -> Result<TusLock<'_>, AppError> {
+        let mut in_progress = self.in_progress
+            .lock()
+            .map_err(|_| AppError::ThreadError("tus lock poisoned".to_string()))?;
+
+        if !in_progress.insert(id.to_string()) {
+            return Err(AppError::Conflict("Upload is already being written".to_string()));
+        }
+
+        Ok(TusLock { manager: self, id: id.to_string() })
+    }
+
+    /// Appends the request body to the staged data, returning the new offset.
+    ///
+    /// Every chunk is flushed to disk as it arrives, so a dropped connection keeps
+    /// whatever was received and the client can resume from there.
+    pub async fn append(&self, id: &str, length: u64, offset: u64, mut payload: web::Payload) -> Result<u64, AppError> {
+        let mut target = std::fs::OpenOptions::new()
+            .append(true)
+            .open(self.data_path(id))?;
+
+        let mut offset = offset;
+
+        while let Some(chunk) = payload.next().await {
+            let data = chunk.map_err(|e| AppError::UploadFailed(e.to_string()))?;
+
+            if offset + data.len() as u64 > length {
+                return Err(AppError::InvalidRequest("Chunk exceeds Upload-Length".to_string()));
+            }
+
+            offset += data.len() as u64;
+
+            target = web::block(move || target.write_all(data.bytes()).map(|_| target)).await?;
+        }
+
+        Ok(offset)
+    }
+
+    /// Deletes the staged files of an upload, once archived or cancelled.
+    pub fn remove(&self, id: &str) -> Result<(), AppError> {
+        for path in [self.data_path(id), self.info_path(id)] {
+            if let Err(e) = std::fs::remove_file(path) {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    return Err(e.into());
+                }
+            }
+        }
+
+        self.release(id);
+
+        Ok(())
+    }
+
+    /// Deletes the uploads not written to for longer than the expiry, and the leftovers of uploads
+    /// interrupted by a crash, returning how many were removed.
+    pub fn remove_expired(&self) -> Result<usize, AppError> {
+        let now = SystemTime::now();
+
+        let mut ids = HashSet::new();
+
+        for entry in std::fs::read_dir(&self.staging)? {
+            let path = entry?.path();
+
+            if let Some(id) = path.file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_suffix(".info").or_else(|| name.strip_suffix(".bin"))) {
+                ids.insert(id.to_string());
+            }
+        }
+
+        let mut removed = 0;
+
+        for id in ids {
+            // Uploads being written are not abandoned.
+            let _lock = match self.lock(&id) {
+                Ok(lock) => lock,
+                Err(_) => continue,
+            };
+
+            let stale = !self.info_path(&id).exists()
+                || !self.data_path(&id).exists()
+                || self.expires(&id)?.is_some_and(|expires| expires <= now);
+
+            if stale {
+                self.remove(&id)?;
+
+                removed += 1;
+            }
+        }
+
+        Ok(removed)
+    }
+}
+
+fn expires_header(expires: SystemTime) -> String {
+    HttpDate::from(expires).to_string()
+}
+
+/// Parses the `Upload-Metadata` header: comma separated `key base64(value)` pairs.
+pub fn parse_metadata(header: &str) -> Result<HashMap<String, String>, AppError> {
+    let mut metadata = HashMap::new();
+
+    for pair in header.split(',').map(|p| p.trim()).filter(|p| !p.is_empty()) {
+        let (key, value) = match pair.split_once(' ') {
+            Some((key, encoded)) => {
+                let decoded = base64::engine::general_purpose::STANDARD
+                    .decode(encoded.trim())
+                    .map_err(|_| AppError::InvalidRequest(format!("Invalid metadata value for {}", key)))?;
+
+                (key, String::from_utf8_lossy(&decoded).to_string())
+            }
+            None => (pair, String::new()),
+        };
+
+        metadata.insert(key.to_string(), value);
+    }
+
+    Ok(metadata)
+}
+
+fn header_value<'a>(req: &'a HttpRequest, name: &str) -> Option<&'a str> {
+    req.headers().get(name).and_then(|v| v.to_str().ok())
+}
+
+fn check_version(req: &HttpRequest) -> Result<(), AppError> {
+    match header_value(req, "Tus-Resumable") {
+        Some(TUS_VERSION) => Ok(()),
+        _ => Err(AppError::PreconditionFailed(format!("Only tus {} is supported", TUS_VERSION))),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UploadPath {
+    id: String,
+}
+
+pub async fn options(data: web::Data<AppData>) -> HttpResponse {
+    HttpResponse::NoContent()
+        .header("Tus-Version", TUS_VERSION)
+        .header("Tus-Extension", TUS_EXTENSIONS)
+        .header("Tus-Max-Size", data.tus().max_size().to_string())
+        .finish()
+}
+
+pub async fn create(req: HttpRequest, data: web::Data<AppData>) -> Result<HttpResponse, AppError> {
+    check_version(&req)?;
+
+    let length = header_value(&req, "Upload-Length")
+        .and_then(|v| v.parse::<u64>().ok())
+        .ok_or_else(|| AppError::InvalidRequest("Missing valid Upload-Length".to_string()))?;
+
+    let metadata = header_value(&req, "Upload-Metadata")
+        .map(parse_metadata)
+        .transpose()?
+        .unwrap_or_default();
+
+    UploadOptions::from_fields(&metadata)?;
+
+    let uploader = AuthenticatedUser::from_request(&req);
+
+    let (manager, tus) = (data.manager(), data.tus());
+
+    let (id, expires) = web::block(move || {
+        let reservation = manager.reserve(uploader.as_deref(), length)?;
+
+        let id = tus.create(length, metadata, uploader)?;
+
+        tus.reserve(&id, reservation);
+
+        let expires = tus.expires(&id)?;
+
+        Ok::<_, AppError>((id, expires))
+    })
+        .await
+        .map_err(AppError::from)?;
+
+    debug!("tus upload created: {} ({} bytes)", id, length);
+
+    let mut response = HttpResponse::Created();
+
+    response.header("Location", format!("{}/{}", req.path().trim_end_matches('/'), id));
+
+    if let Some(expires) = expires {
+        response.header("Upload-Expires", expires_header(expires));
+    }
+
+    Ok(response.finish())
+}
+
+pub async fn head(req: HttpRequest, path: web::Path<UploadPath>, data: web::Data<AppData>) -> Result<HttpResponse, AppError> {
+    let (tus, id, user) = (data.tus(), path.into_inner().id, AuthenticatedUser::from_request(&req));
+
+    let (upload, offset, expires) = web::block(move || {
+        let (upload, offset) = tus.get(&id)?;
+
+        upload.check_uploader(user.as_deref())?;
+
+        Ok::<_, AppError>((upload, offset, tus.expires(&id)?))
+    })
+        .await
+        .map_err(AppError::from)?;
+
+    let mut response = HttpResponse::Ok();
+
+    response
+        .header("Upload-Offset", offset.to_string())
+        .header("Upload-Length", upload.length.to_string())
+        .header("Cache-Control", "no-store");
+
+    if let Some(expires) = expires {
+        response.header("Upload-Expires", expires_header(expires));
+    }
+
+    Ok(response.finish())
+}
+
+pub async fn patch(
+    req: HttpRequest,
+    path: web::Path<UploadPath>,
+    payload: web::Payload,
+    data: web::Data<AppData>,
+) -> Result<HttpResponse, AppError> {
+    check_version(&req)?;
+
+    if header_value(&req, "Content-Type") != Some(OFFSET_CONTENT_TYPE) {
+        return Err(AppError::UnsupportedMediaType(format!("Content-Type must be {}", OFFSET_CONTENT_TYPE)));
+    }
+
+    let tus = data.tus();
+
+    let _lock = tus.lock(&path.id)?;
+
+    let offset = header_value(&req, "Upload-Offset")
+        .and_then(|v| v.parse::<u64>().ok())
+        .ok_or_else(|| AppError::InvalidRequest("Missing valid Upload-Offset".to_string()))?;
+
+    let (manager, staged, id, user) = (data.manager(), tus.clone(), path.id.clone(), AuthenticatedUser::from_request(&req));
+
+    let upload = web::block(move || {
+        let (upload, current_offset) = staged.get(&id)?;
+
+        upload.check_uploader(user.as_deref())?;
+
+        if offset != current_offset {
+            return Err(AppError::Conflict(format!("Upload-Offset should be {}", current_offset)));
+        }
+
+        if !staged.is_reserved(&id) {
+            staged.reserve(&id, manager.reserve(upload.uploader.as_deref(), upload.length)?);
+        }
+
+        Ok(upload)
+    })
+        .await
+        .map_err(AppError::from)?;
+
+    let offset = tus.append(&path.id, upload.length, offset, payload).await?;
+
+    let mut response = HttpResponse::NoContent();
+
+    response.header("Upload-Offset", offset.to_string());
+
+    if offset == upload.length {
+        let filename = upload.filename().unwrap_or_else(generate_random_link);
+
+        let link = data.manager()
+            .store_file(tus.data_path(&path.id), filename, upload.options()?)
+            .await?;
+
+        info!("tus upload {} finished as {}", path.id, link);
+
+        response.header("Fshare-Link", link.as_str());
+
+        let (staged, id) = (tus.clone(), path.id.clone());
+
+        web::block(move || staged.remove(&id)).await.map_err(AppError::from)?;
+    } else {
+        let (staged, id) = (tus.clone(), path.id.clone());
+
+        if let Some(expires) = web::block(move || staged.expires(&id)).await.map_err(AppError::from)? {
+            response.header("Upload-Expires", expires_header(expires));
+        }
+    }
+
+    Ok(response.finish())
+}
+
+pub async fn terminate(
+    req: HttpRequest,
+    path: web::Path<UploadPath>,
+    data: web::Data<AppData>,
+) -> Result<HttpResponse, AppError> {
+    check_version(&req)?;
+
+    let tus = data.tus();
+
+    let _lock = tus.lock(&path.id)?;
+
+    let (staged, id, user) = (tus.clone(), path.id.clone(), AuthenticatedUser::from_request(&req));
+
+    web::block(move || {
+        staged.get(&id)?.0.check_uploader(user.as_deref())?;
+
+        staged.remove(&id)
+    })
+        .await
+        .map_err(AppError::from)?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[test]
+fn parse_metadata_works() {
+    let metadata = parse_metadata("filename d29ybGRfZG9taW5hdGlvbl9wbGFuLnBkZg==,is_confidential").unwrap();
+
+    assert_eq!(metadata.get("filename").map(|s| s.as_str()), Some("world_domination_plan.pdf"));
+    assert_eq!(metadata.get("is_confidential").map(|s| s.as_str()), Some(""));
+}
+
+#[test]
+fn abandoned_uploads_expire() {
+    let staging = std::env::temp_dir().join(format!("fshare-tus-{}", generate_random_link()));
+
+    let kept = TusManager::new(staging.clone(), 1024, None).unwrap();
+    let id = kept.create(10, HashMap::new(), None).unwrap();
+
+    assert_eq!(kept.expires(&id).unwrap(), None);
+    assert_eq!(kept.remove_expired().unwrap(), 0);
+
+    // Leftovers of uploads interrupted by a crash go even when uploads never expire.
+    std::fs::write(kept.data_path("orphan"), b"data").unwrap();
+    std::fs::write(kept.info_path("finished"), b"{}").unwrap();
+
+    assert_eq!(kept.remove_expired().unwrap(), 2);
+    assert!(kept.get(&id).is_ok());
+
+    let expiring = TusManager::new(staging.clone(), 1024, Some(Duration::ZERO)).unwrap();
+
+    assert!(matches!(expiring.get(&id), Err(AppError::FileNotFound)));
+    assert_eq!(expiring.remove_expired().unwrap(), 1);
+    assert!(!expiring.data_path(&id).exists());
+    assert_eq!(std::fs::read_dir(&staging).unwrap().count(), 0);
+
+    std::fs::remove_dir_all(staging).unwrap();
+}
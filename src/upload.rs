@@ -1,21 +1,49 @@
-use std::collections::HashMap;
-use std::io::Write;
-use std::path::PathBuf;
-use std::time::SystemTime;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{Read, Seek, Write};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::path::{Path, PathBuf};
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime};
 
 use actix_files::NamedFile;
 use actix_multipart::Multipart;
-use actix_web::web;
-use actix_web::web::Buf;
-use futures::{StreamExt, TryStreamExt};
+use actix_web::dev::{Body, BodySize, MessageBody, ResponseBody, SizedStream};
+use actix_web::http::header::{self, Charset, ContentDisposition, DispositionParam, DispositionType, ExtendedValue};
+use actix_web::web::{Buf, Bytes};
+use actix_web::http::StatusCode;
+use actix_web::{HttpMessage, HttpRequest, HttpResponse, web};
+use base64::Engine;
+use futures::channel::mpsc;
+use futures::{SinkExt, StreamExt, TryStreamExt};
 use serde::Serialize;
+use sha2::{Digest, Sha256};
 
+use crate::app_configs::{CompressionConfiguration, QuotaConfiguration, UploadConfiguration};
+use crate::auth_middleware::AuthenticatedUser;
 use crate::errors::AppError;
-use crate::utils::{generate_random_link, is_link_valid};
+use crate::blobs::{blob_record, BlobStore, StagedBlob};
+use crate::index::{IndexedFile, UploadIndex, UploadRecord, UploadRequest};
+use crate::metadata::{BlobFile, UploadMetadata};
+use crate::password::{hash_password, verify_password};
+use crate::storage::{get_storage, LocalStorage, SeekableRead, StorageBackend};
+use crate::utils::{generate_random_link, is_link_valid, parse_duration, parse_size};
+use crate::zip_stream::{ZipEntry, ZipSource, ZipStream};
+
+/// Upper bound for plain (non file) form fields sent with an upload.
+const MAX_FIELD_SIZE: usize = 1024;
+
+/// Size of the chunks read from an archive entry, and how many of them may wait to be sent.
+const ENTRY_CHUNK_SIZE: usize = 64 * 1024;
+const ENTRY_CHANNEL_SIZE: usize = 4;
+
+const MAX_LABEL_LENGTH: usize = 200;
+
+/// File added to every archive with the SHA-256 of the others, in `sha256sum` format.
+const CHECKSUMS_FILE: &str = "SHA256SUMS";
 
 #[derive(Serialize, Clone)]
 pub enum FileType {
-    Regular,
     Archive(String),
 }
 
@@ -25,75 +53,1196 @@ pub struct FileInfo {
     file_type: FileType,
     size: u64,
     created: u64,
+    expires_at: Option<u64>,
+    protected: bool,
+    remaining_downloads: Option<u64>,
+    uploader: Option<String>,
+    label: Option<String>,
 }
 
 impl FileInfo {
-    fn new(name: String, file_type: FileType, size: u64, created: u64) -> Self {
+    fn new(record: &UploadRecord, file: IndexedFile) -> Self {
         FileInfo {
-            name,
-            file_type,
-            size,
-            created,
+            name: file.name,
+            file_type: FileType::Archive(record.link.clone()),
+            size: file.size,
+            created: record.metadata.created,
+            expires_at: record.metadata.expires_at,
+            protected: record.metadata.password_hash.is_some(),
+            remaining_downloads: record.metadata.remaining_downloads(),
+            uploader: record.metadata.uploader.clone(),
+            label: record.metadata.label.clone(),
         }
     }
 }
 
+/// What a visitor gave to open a password protected link.
 #[derive(Clone, Debug)]
+pub enum LinkKey {
+    Nothing,
+    Password(String),
+    /// The password was given before, as checked by the caller.
+    Unlocked,
+}
+
+/// What a link serves: its whole archive, or a single file of it.
+pub struct Download {
+    content: DownloadContent,
+    /// Limit of the link, checked by `count`.
+    counter: Option<DownloadCounter>,
+    /// Set once `count` took one of the downloads left.
+    counted: Option<CountedDownload>,
+    /// SHA-256 of a single file, sent as `Digest` and `ETag` headers.
+    sha256: Option<String>,
+}
+
+/// Counts downloads of a link with a download limit.
+struct DownloadCounter {
+    index: Arc<UploadIndex>,
+    storage: Arc<dyn StorageBackend>,
+    blobs: Arc<BlobStore>,
+    record: UploadRecord,
+}
+
+/// A download counted when it started. Once over, the link is removed if it was the last allowed one; failing on
+/// our side gives the download back.
+struct CountedDownload {
+    counter: DownloadCounter,
+    downloads: u64,
+}
+
+impl CountedDownload {
+    fn finish(self, failed: bool) {
+        actix_web::rt::spawn(async move {
+            let link = self.counter.record.link.clone();
+
+            if let Err(e) = web::block(move || self.run(failed)).await {
+                error!("Error counting download of {}: {:?}", link, e);
+            }
+        });
+    }
+
+    fn run(self, failed: bool) -> Result<(), AppError> {
+        let DownloadCounter { index, storage, blobs, record } = self.counter;
+        let link = record.link;
+
+        if failed {
+            index.refund_download(&link)?;
+
+            let metadata = UploadMetadata { downloads: self.downloads - 1, ..record.metadata };
+
+            return metadata.write(storage.as_ref(), &link);
+        }
+
+        let max_downloads = record.metadata.max_downloads.unwrap_or_default();
+
+        if self.downloads >= max_downloads {
+            info!("Link {} reached its {} downloads, removing it", link, max_downloads);
+
+            let released = index.remove(&link)?;
+
+            return Cleanup { storage, blobs, link, released }.run();
+        }
+
+        Ok(())
+    }
+}
+
+/// Body of a counted download, finishing it once sent or dropped. Downloads interrupted by the client still count.
+struct CountedBody {
+    body: ResponseBody<Body>,
+    counted: Option<CountedDownload>,
+    failed: bool,
+}
+
+impl MessageBody for CountedBody {
+    fn size(&self) -> BodySize {
+        self.body.size()
+    }
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Bytes, actix_web::Error>>> {
+        let next = Pin::new(&mut self.body).poll_next(cx);
+
+        if let Poll::Ready(Some(Err(_))) = next {
+            self.failed = true;
+        }
+
+        next
+    }
+}
+
+impl Drop for CountedBody {
+    fn drop(&mut self) {
+        if let Some(counted) = self.counted.take() {
+            counted.finish(self.failed);
+        }
+    }
+}
+
+/// Objects of an upload taken out of the index, with the blobs it was the last to reference.
+struct Cleanup {
+    storage: Arc<dyn StorageBackend>,
+    blobs: Arc<BlobStore>,
+    link: String,
+    released: Vec<String>,
+}
+
+impl Cleanup {
+    fn run(&self) -> Result<(), AppError> {
+        remove_objects(self.storage.as_ref(), &self.link)?;
+
+        self.blobs.release(&self.released)
+    }
+}
+
+enum DownloadContent {
+    /// Archive or blob on the local disk, served with support for range requests.
+    File { file: Box<NamedFile>, name: String },
+    /// Archive read from a remote storage backend or assembled from blobs, or a single blob.
+    Archive { reader: Box<dyn Read + Send>, name: String, size: u64 },
+    /// A single file of an archive, streamed out without extracting the rest of the archive.
+    Entry { archive: Box<dyn SeekableRead>, name: String, size: u64 },
+}
+
+impl Download {
+    /// Takes one of the downloads left of a link with a download limit, refusing the download when none is left.
+    /// Call it from `web::block` before `into_response`, except for requests that send no content.
+    pub fn count(&mut self) -> Result<(), AppError> {
+        let counter = match self.counter.take() {
+            Some(counter) => counter,
+            None => return Ok(()),
+        };
+
+        let link = counter.record.link.clone();
+
+        let downloads = counter.index.record_download(&link)?.ok_or(AppError::Gone)?;
+
+        let metadata = UploadMetadata { downloads, ..counter.record.metadata.clone() };
+
+        if let Err(e) = metadata.write(counter.storage.as_ref(), &link) {
+            error!("Error saving download count of {}: {:?}", link, e);
+        }
+
+        self.counted = Some(CountedDownload { counter, downloads });
+
+        Ok(())
+    }
+
+    pub fn into_response(self, req: &HttpRequest) -> Result<HttpResponse, actix_web::Error> {
+        let counted = self.counted;
+
+        let condition = match self.sha256.as_deref() {
+            Some(sha256) => check_conditions(req, sha256),
+            None => Condition::Content { whole: false },
+        };
+
+        let mut response = match (condition, self.content) {
+            (Condition::Status(status), _) => HttpResponse::build(status).finish(),
+            (Condition::Content { whole }, DownloadContent::File { file, name }) => {
+                let response = match whole {
+                    true => whole_file(&file, &name),
+                    false => file.into_response(req),
+                };
+
+                match response {
+                    Ok(response) => response,
+                    Err(e) => {
+                        if let Some(counted) = counted {
+                            counted.finish(true);
+                        }
+
+                        return Err(e);
+                    }
+                }
+            }
+            (_, DownloadContent::Archive { reader, name, size }) => {
+                stream_response(&name, size, move |sender| send_chunks(reader, sender))
+            }
+            (_, DownloadContent::Entry { archive, name, size }) => {
+                stream_response(&name.clone(), size, move |sender| {
+                    let mut archive = zip::ZipArchive::new(archive)?;
+
+                    let entry = archive.by_name(&name)?;
+
+                    send_chunks(entry, sender)
+                })
+            }
+        };
+
+        if let Some(digest) = self.sha256.as_deref().and_then(digest_headers) {
+            let headers = response.headers_mut();
+
+            headers.insert(header::HeaderName::from_static("digest"), digest.0);
+            headers.insert(header::ETAG, digest.1);
+        }
+
+        match counted {
+            // Nothing was sent for a conditional request or an unsatisfiable range.
+            Some(counted) if !response.status().is_success() => {
+                counted.finish(true);
+
+                Ok(response)
+            }
+            Some(counted) => {
+                Ok(response.map_body(|_, body| ResponseBody::Other(Body::from_message(CountedBody { body, counted: Some(counted), failed: false }))))
+            }
+            None => Ok(response),
+        }
+    }
+}
+
+/// Sends what `producer` reads from the blocking pool, as reading from the storage blocks.
+fn stream_response<F>(name: &str, size: u64, producer: F) -> HttpResponse
+    where F: FnOnce(&mut mpsc::Sender<Result<Bytes, AppError>>) -> Result<(), AppError> + Send + 'static {
+    let (content_type, disposition) = content_headers(name);
+
+    let (mut sender, receiver) = mpsc::channel(ENTRY_CHANNEL_SIZE);
+
+    let name = name.to_string();
+
+    // The blocking pool bounds how many downloads are read at once, the others wait for a thread.
+    actix_web::rt::spawn(async move {
+        let streamed = web::block(move || {
+            if let Err(e) = producer(&mut sender) {
+                error!("Error streaming {}: {:?}", name, e);
+
+                let _ = futures::executor::block_on(sender.send(Err(e)));
+            }
+
+            Ok::<_, AppError>(())
+        });
+
+        if let Err(e) = streamed.await {
+            error!("Error streaming a download: {:?}", e);
+        }
+    });
+
+    HttpResponse::Ok()
+        .content_type(content_type.to_string())
+        .set(disposition)
+        .body(Body::from_message(SizedStream::new(size, receiver.map_err(actix_web::Error::from))))
+}
+
+/// Content type of a file, and whether browsers should show it or save it.
+fn content_headers(name: &str) -> (mime::Mime, ContentDisposition) {
+    let content_type = mime_guess::from_path(name).first_or_octet_stream();
+
+    let disposition = match content_type.type_() {
+        mime::IMAGE | mime::TEXT | mime::VIDEO => DispositionType::Inline,
+        _ => DispositionType::Attachment,
+    };
+
+    let filename = Path::new(name)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| name.to_string());
+
+    let mut parameters = vec![DispositionParam::Filename(filename.clone())];
+
+    if !filename.is_ascii() {
+        parameters.push(DispositionParam::FilenameExt(ExtendedValue {
+            charset: Charset::Ext("UTF-8".to_string()),
+            language_tag: None,
+            value: filename.into_bytes(),
+        }));
+    }
+
+    (content_type, ContentDisposition { disposition, parameters })
+}
+
+fn send_chunks<R: Read>(mut reader: R, sender: &mut mpsc::Sender<Result<Bytes, AppError>>) -> Result<(), AppError> {
+    let mut buffer = vec![0; ENTRY_CHUNK_SIZE];
+
+    loop {
+        let read = reader.read(&mut buffer)?;
+
+        if read == 0 {
+            return Ok(());
+        }
+
+        let chunk = Bytes::copy_from_slice(&buffer[..read]);
+
+        if futures::executor::block_on(sender.send(Ok(chunk))).is_err() {
+            debug!("Client went away while streaming");
+            return Ok(());
+        }
+    }
+}
+
+fn archive_key(link: &str) -> String {
+    format!("{}.zip", link)
+}
+
+/// Deletes the archive and metadata file of an upload from the storage.
+fn remove_objects(storage: &dyn StorageBackend, link: &str) -> Result<(), AppError> {
+    storage.delete(&archive_key(link))?;
+    storage.delete(&UploadMetadata::key(link))
+}
+
+/// Entries of the archive streamed for a deduplicated upload, with the directories of its files. `key` gives the
+/// object holding the content of a blob.
+fn zip_entries(blobs: &[BlobFile], key: fn(&str) -> String) -> Vec<ZipEntry> {
+    let mut paths = ArchivePaths::new();
+    let mut entries = Vec::new();
+
+    for blob in blobs {
+        let (directories, name) = paths.add(&blob.name);
+
+        entries.extend(directories.into_iter().map(ZipEntry::directory));
+        entries.push(ZipEntry { name, source: ZipSource::Object(key(&blob.sha256)), size: blob.size, crc32: blob.crc32 });
+    }
+
+    let checksums = blobs.iter().map(|blob| (blob.name.clone(), blob.sha256.clone())).collect();
+
+    entries.push(ZipEntry::bytes(CHECKSUMS_FILE.to_string(), checksums_file(&checksums)));
+
+    entries
+}
+
+/// Fills in the checksums of the files of an archive, recorded in its metadata.
+fn with_checksums(mut files: Vec<IndexedFile>, checksums: &BTreeMap<String, String>) -> Vec<IndexedFile> {
+    for file in files.iter_mut() {
+        file.sha256 = checksums.get(&file.name).cloned();
+    }
+
+    files
+}
+
+/// Content of the `SHA256SUMS` file of an archive, as read by `sha256sum -c`.
+fn checksums_file(checksums: &BTreeMap<String, String>) -> Vec<u8> {
+    checksums
+        .iter()
+        .map(|(name, sha256)| format!("{}  {}\n", sha256, name))
+        .collect::<String>()
+        .into_bytes()
+}
+
+/// SHA-256 a client announced for a form file, from a `Content-Digest: sha-256=:<base64>:` or
+/// `Digest: sha-256=<base64>` header of its part.
+fn expected_digest(headers: &actix_web::http::HeaderMap) -> Result<Option<String>, AppError> {
+    let values = headers.get_all("content-digest").chain(headers.get_all("digest"));
+
+    for value in values {
+        let value = value.to_str().map_err(|_| AppError::InvalidRequest("Invalid digest header".to_string()))?;
+
+        for digest in value.split(',') {
+            let (algorithm, encoded) = match digest.split_once('=') {
+                Some((algorithm, encoded)) if algorithm.trim().eq_ignore_ascii_case("sha-256") => (algorithm, encoded),
+                _ => continue,
+            };
+
+            let decoded = base64::engine::general_purpose::STANDARD
+                .decode(encoded.trim().trim_matches(':'))
+                .ok()
+                .filter(|decoded| decoded.len() == 32)
+                .ok_or_else(|| AppError::InvalidRequest(format!("Invalid {} digest: {}", algorithm.trim(), encoded)))?;
+
+            return Ok(Some(hex(&decoded)));
+        }
+    }
+
+    Ok(None)
+}
+
+/// How to answer a request for content whose `ETag` is a SHA-256.
+enum Condition {
+    /// Send this status instead of the content.
+    Status(StatusCode),
+    /// Send the content, whole when `NamedFile` would misread the request: it knows neither this `ETag` nor `If-Range`.
+    Content { whole: bool },
+}
+
+/// Evaluates the `If-Match`, `If-None-Match` and `If-Range` headers of `req` against the `ETag` of `sha256`.
+fn check_conditions(req: &HttpRequest, sha256: &str) -> Condition {
+    let etag = header::EntityTag::strong(sha256.to_string());
+
+    let matched = match req.get_header::<header::IfMatch>() {
+        Some(header::IfMatch::Items(items)) => Some(items.iter().any(|item| item.strong_eq(&etag))),
+        _ => None,
+    };
+
+    if matched == Some(false) {
+        return Condition::Status(StatusCode::PRECONDITION_FAILED);
+    }
+
+    let not_modified = match req.get_header::<header::IfNoneMatch>() {
+        Some(header::IfNoneMatch::Any) => true,
+        Some(header::IfNoneMatch::Items(items)) => items.iter().any(|item| item.weak_eq(&etag)),
+        None => false,
+    };
+
+    if not_modified {
+        return Condition::Status(StatusCode::NOT_MODIFIED);
+    }
+
+    // A range of an older version would be spliced into the newer one, only a matching ETag keeps it.
+    let ranged = match req.get_header::<header::IfRange>() {
+        Some(header::IfRange::EntityTag(tag)) => tag.strong_eq(&etag),
+        Some(header::IfRange::Date(_)) => false,
+        None => true,
+    };
+
+    Condition::Content { whole: matched.is_some() || (!ranged && req.headers().contains_key(header::RANGE)) }
+}
+
+/// Sends a local file whole, whatever the request headers ask for.
+fn whole_file(file: &NamedFile, name: &str) -> Result<HttpResponse, actix_web::Error> {
+    let size = file.file().metadata()?.len();
+    let reader = file.file().try_clone()?;
+
+    Ok(stream_response(name, size, move |sender| send_chunks(reader, sender)))
+}
+
+/// `Digest` and `ETag` header values of a SHA-256 in hex.
+fn digest_headers(sha256: &str) -> Option<(header::HeaderValue, header::HeaderValue)> {
+    let bytes = (0..sha256.len())
+        .step_by(2)
+        .map(|i| sha256.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+        .collect::<Option<Vec<u8>>>()?;
+
+    let digest = format!("sha-256={}", base64::engine::general_purpose::STANDARD.encode(bytes));
+
+    Some((header::HeaderValue::from_str(&digest).ok()?, header::HeaderValue::from_str(&format!("\"{}\"", sha256)).ok()?))
+}
+
+fn sha256_of<R: Read>(mut reader: R) -> Result<String, AppError> {
+    let mut sha256 = Sha256::new();
+
+    std::io::copy(&mut reader, &mut sha256)?;
+
+    Ok(hex(&sha256.finalize()))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Where the files of an upload form are written, each hashed as it is written.
+enum UploadTarget {
+    Archive {
+        zipper: zip::ZipWriter<std::fs::File>,
+        current: Option<(String, Sha256)>,
+        checksums: BTreeMap<String, String>,
+    },
+    /// Files staged one by one in `directory`, each named after its hash once complete.
+    Blobs { directory: PathBuf, current: Option<(String, StagedBlob)>, files: Vec<BlobFile> },
+}
+
+/// What was written by an `UploadTarget`: the SHA-256 of every file, and the blobs to store when deduplicating.
+struct WrittenFiles {
+    checksums: BTreeMap<String, String>,
+    blobs: Option<Vec<BlobFile>>,
+}
+
+impl UploadTarget {
+    /// Starts writing an archive at `archive_path`, or staging blobs in `blob_directory` when deduplicating.
+    fn create(dedupe: bool, archive_path: PathBuf, blob_directory: PathBuf) -> Result<Self, AppError> {
+        if dedupe {
+            std::fs::create_dir_all(&blob_directory)?;
+
+            return Ok(UploadTarget::Blobs { directory: blob_directory, current: None, files: Vec::new() });
+        }
+
+        Ok(UploadTarget::Archive {
+            zipper: zip::ZipWriter::new(std::fs::File::create(archive_path)?),
+            current: None,
+            checksums: BTreeMap::new(),
+        })
+    }
+
+    fn add_directory(&mut self, name: String) -> Result<(), AppError> {
+        if let UploadTarget::Archive { zipper, .. } = self {
+            zipper.add_directory(name, Default::default())?;
+        }
+
+        Ok(())
+    }
+
+    fn start_file(&mut self, name: String, options: zip::write::FileOptions) -> Result<(), AppError> {
+        match self {
+            UploadTarget::Archive { zipper, current, .. } => {
+                zipper.start_file(name.clone(), options)?;
+
+                *current = Some((name, Sha256::new()));
+            }
+            UploadTarget::Blobs { directory, current, files } => {
+                *current = Some((name, StagedBlob::create(directory.join(format!("{}.part", files.len())))?));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Completes the current file, returning its SHA-256.
+    fn end_file(&mut self) -> Result<String, AppError> {
+        let no_file = || AppError::ThreadError("No file started".to_string());
+
+        match self {
+            UploadTarget::Archive { current, checksums, .. } => {
+                let (name, sha256) = current.take().ok_or_else(no_file)?;
+                let sha256 = hex(&sha256.finalize());
+
+                checksums.insert(name, sha256.clone());
+
+                Ok(sha256)
+            }
+            UploadTarget::Blobs { current, files, .. } => {
+                let (name, blob) = current.take().ok_or_else(no_file)?;
+                let blob = blob.finish(name)?;
+                let sha256 = blob.sha256.clone();
+
+                files.push(blob);
+
+                Ok(sha256)
+            }
+        }
+    }
+
+    /// Completes the archive, adding a `SHA256SUMS` file.
+    fn finish(self) -> Result<WrittenFiles, AppError> {
+        match self {
+            UploadTarget::Archive { mut zipper, checksums, .. } => {
+                zipper.start_file(CHECKSUMS_FILE, zip::write::FileOptions::default())?;
+                zipper.write_all(&checksums_file(&checksums))?;
+                zipper.finish()?;
+
+                Ok(WrittenFiles { checksums, blobs: None })
+            }
+            UploadTarget::Blobs { files, .. } => {
+                let checksums = files.iter().map(|blob| (blob.name.clone(), blob.sha256.clone())).collect();
+
+                Ok(WrittenFiles { checksums, blobs: Some(files) })
+            }
+        }
+    }
+}
+
+impl Write for UploadTarget {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            UploadTarget::Archive { zipper, current: Some((_, sha256)), .. } => {
+                let written = zipper.write(buf)?;
+
+                sha256.update(&buf[..written]);
+
+                Ok(written)
+            }
+            UploadTarget::Blobs { current: Some((_, blob)), .. } => blob.write(buf),
+            _ => Err(std::io::Error::other("no file started")),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            UploadTarget::Archive { zipper, .. } => zipper.flush(),
+            UploadTarget::Blobs { current: Some((_, blob)), .. } => blob.flush(),
+            UploadTarget::Blobs { current: None, .. } => Ok(()),
+        }
+    }
+}
+
+/// Storage used by a user, against their quota.
+#[derive(Debug, Serialize)]
+pub struct Quota {
+    pub used: u64,
+    pub limit: Option<u64>,
+    pub remaining: Option<u64>,
+}
+
+fn quota_exceeded(remaining: Option<u64>) -> AppError {
+    AppError::QuotaExceeded(format!("Storage quota exceeded, {} bytes left", remaining.unwrap_or_default()))
+}
+
+/// Bytes reserved by each user for their uploads in progress.
+type Reservations = Mutex<HashMap<String, u64>>;
+
+/// Quota set aside for an upload in progress, released when dropped.
+///
+/// Uploads reserve what they receive before writing it, checked under one lock against what is stored and
+/// reserved, so concurrent uploads cannot exceed a quota together. Drop it once the upload is indexed.
+#[derive(Debug)]
+pub struct QuotaReservation {
+    index: Arc<UploadIndex>,
+    reservations: Arc<Reservations>,
+    uploader: String,
+    limit: u64,
+    reserved: u64,
+}
+
+impl QuotaReservation {
+    /// Counts `size` more bytes of the upload, failing when they do not fit in the quota.
+    pub fn add(&mut self, size: u64) -> Result<(), AppError> {
+        let mut reservations = self.reservations
+            .lock()
+            .map_err(|_| AppError::ThreadError("quota lock poisoned".to_string()))?;
+
+        let reserved = reservations.entry(self.uploader.clone()).or_default();
+
+        let stored = self.index.usage(&self.uploader)?;
+
+        let others = *reserved - self.reserved;
+
+        if stored + *reserved + size > self.limit {
+            return Err(quota_exceeded(Some(self.limit.saturating_sub(stored + others))));
+        }
+
+        *reserved += size;
+        self.reserved += size;
+
+        Ok(())
+    }
+}
+
+impl Drop for QuotaReservation {
+    fn drop(&mut self) {
+        if let Ok(mut reservations) = self.reservations.lock() {
+            if let Some(reserved) = reservations.get_mut(&self.uploader) {
+                *reserved -= self.reserved;
+
+                if *reserved == 0 {
+                    reservations.remove(&self.uploader);
+                }
+            }
+        }
+    }
+}
+
+/// Path of a form file inside the archive. Folder uploads send the relative path
+/// (`photos/2023/a.jpg`) as the field name, browsers only send `a.jpg` as the filename.
+fn relative_path(field_name: Option<&str>, filename: &str) -> String {
+    match field_name {
+        Some(name) if name.ends_with(&format!("/{}", filename)) => name.to_string(),
+        _ => filename.to_string(),
+    }
+}
+
+/// Makes a client supplied path safe to use inside the archive, rejecting `..` and absolute paths.
+fn sanitize_path(path: &str) -> Result<String, AppError> {
+    if path.starts_with('/') || path.starts_with('\\') || (path.get(1..2) == Some(":") && path.starts_with(|c: char| c.is_ascii_alphabetic())) {
+        return Err(AppError::InvalidRequest(format!("Absolute paths are not allowed: {}", path)));
+    }
+
+    let mut components = Vec::new();
+
+    for component in path.split(['/', '\\']) {
+        match component {
+            "" | "." => continue,
+            ".." => return Err(AppError::InvalidRequest(format!("Invalid path: {}", path))),
+            component => {
+                let component = sanitize_filename::sanitize(component);
+
+                if component.is_empty() {
+                    return Err(AppError::InvalidRequest(format!("Invalid path: {}", path)));
+                }
+
+                components.push(component);
+            }
+        }
+    }
+
+    if components.is_empty() {
+        return Err(AppError::InvalidRequest(format!("Invalid path: {}", path)));
+    }
+
+    Ok(components.join("/"))
+}
+
+/// Names already used in an archive being written.
+#[derive(Debug, Default)]
+struct ArchivePaths {
+    directories: HashSet<String>,
+    files: HashSet<String>,
+}
+
+impl ArchivePaths {
+    /// Paths of a new archive, whose `SHA256SUMS` file is added last.
+    fn new() -> Self {
+        let mut paths = ArchivePaths::default();
+
+        paths.files.insert(CHECKSUMS_FILE.to_string());
+
+        paths
+    }
+
+    /// Adds a file, returning the directory entries it still needs and a name no other entry uses.
+    fn add(&mut self, path: &str) -> (Vec<String>, String) {
+        let (parent, filename) = match path.rfind('/') {
+            Some(index) => (&path[..index + 1], &path[index + 1..]),
+            None => ("", path),
+        };
+
+        let mut directories = Vec::new();
+
+        for (index, _) in parent.match_indices('/') {
+            let directory = format!("{}/", &parent[..index]);
+
+            if self.directories.insert(directory.clone()) {
+                directories.push(directory);
+            }
+        }
+
+        let (stem, extension) = match filename.rfind('.') {
+            Some(index) if index > 0 => filename.split_at(index),
+            _ => (filename, ""),
+        };
+
+        let mut candidate = path.to_string();
+        let mut copy = 0;
+
+        while self.files.contains(&candidate) || self.directories.contains(&format!("{}/", candidate)) {
+            copy += 1;
+            candidate = format!("{}{} ({}){}", parent, stem, copy, extension);
+        }
+
+        self.files.insert(candidate.clone());
+
+        (directories, candidate)
+    }
+}
+
+/// Whether files of this type are already compressed, so compressing them again only costs time.
+fn is_compressed(content_type: &mime::Mime) -> bool {
+    if content_type.suffix().map(|suffix| suffix.as_str()) == Some("zip") {
+        return true;
+    }
+
+    match (content_type.type_(), content_type.subtype().as_str()) {
+        (mime::IMAGE, subtype) => !matches!(subtype, "svg" | "bmp" | "x-ms-bmp" | "tiff" | "x-icon"),
+        (mime::VIDEO, _) => true,
+        (mime::AUDIO, subtype) => !matches!(subtype, "wav" | "x-wav" | "vnd.wave" | "aiff" | "x-aiff"),
+        (mime::APPLICATION, subtype) => matches!(
+            subtype,
+            "zip" | "gzip" | "x-gzip" | "x-bzip2" | "x-xz" | "zstd" | "x-7z-compressed" | "vnd.rar"
+                | "x-rar-compressed" | "java-archive" | "vnd.android.package-archive" | "pdf"
+        ) || subtype.starts_with("vnd.openxmlformats") || subtype.starts_with("vnd.oasis.opendocument"),
+        _ => false,
+    }
+}
+
+/// Settings of a new upload request, sent as plain form fields.
+#[derive(Debug, Default)]
+pub struct RequestOptions {
+    expires_in: Option<Duration>,
+    max_size: Option<u64>,
+    max_files: Option<u64>,
+    label: Option<String>,
+}
+
+impl RequestOptions {
+    pub fn from_fields(fields: &HashMap<String, String>) -> Result<Self, AppError> {
+        let field = |name: &str| fields.get(name).map(|value| value.trim()).filter(|value| !value.is_empty());
+
+        let expires_in = field("expires_in")
+            .map(parse_duration)
+            .transpose()
+            .map_err(AppError::InvalidRequest)?
+            .flatten();
+
+        let max_size = field("max_size")
+            .map(parse_size)
+            .transpose()
+            .map_err(AppError::InvalidRequest)?
+            .flatten();
+
+        let max_files = field("max_files")
+            .map(|value| value.parse::<u64>().ok().filter(|max| *max > 0)
+                .ok_or_else(|| AppError::InvalidRequest(format!("Invalid max_files: {}", value))))
+            .transpose()?;
+
+        Ok(RequestOptions { expires_in, max_size, max_files, label: field("label").map(|label| label.to_string()) })
+    }
+}
+
+/// Settings sent by the uploader as plain form fields next to the files.
+#[derive(Debug, Default)]
+pub struct UploadOptions {
+    expires_in: Option<Option<Duration>>,
+    password: Option<String>,
+    max_downloads: Option<u64>,
+    message: Option<String>,
+    uploader: Option<String>,
+    label: Option<String>,
+}
+
+/// Limits of a single upload, `None` when unlimited.
+#[derive(Debug, Default)]
+struct UploadLimits {
+    /// Quota of the owner, reserved as bytes arrive.
+    quota: Option<QuotaReservation>,
+    max_size: Option<u64>,
+    max_files: Option<u64>,
+}
+
+impl UploadOptions {
+    pub fn from_fields(fields: &HashMap<String, String>) -> Result<Self, AppError> {
+        let expires_in = fields.get("expires_in")
+            .filter(|value| !value.trim().is_empty())
+            .map(parse_duration)
+            .transpose()
+            .map_err(AppError::InvalidRequest)?;
+
+        let password = fields.get("password")
+            .filter(|value| !value.is_empty())
+            .cloned();
+
+        let max_downloads = fields.get("max_downloads")
+            .map(|value| value.trim())
+            .filter(|value| !value.is_empty())
+            .map(|value| value.parse::<u64>().ok().filter(|max| *max > 0)
+                .ok_or_else(|| AppError::InvalidRequest(format!("Invalid max_downloads: {}", value))))
+            .transpose()?;
+
+        let message = fields.get("message")
+            .map(|value| value.trim())
+            .filter(|value| !value.is_empty())
+            .map(|value| value.to_string());
+
+        Ok(UploadOptions { expires_in, password, max_downloads, message, uploader: None, label: None })
+    }
+
+    /// Options of an upload sent through an upload request: only the message is up to the sender.
+    pub fn requested(fields: &HashMap<String, String>, request: &UploadRequest) -> Result<Self, AppError> {
+        Ok(UploadOptions {
+            message: Self::from_fields(fields)?.message,
+            uploader: Some(request.creator.clone()),
+            label: request.label.clone(),
+            ..UploadOptions::default()
+        })
+    }
+
+    /// Sets the authenticated user recorded as the owner of the upload.
+    pub fn with_uploader(mut self, uploader: Option<String>) -> Self {
+        self.uploader = uploader;
+        self
+    }
+}
+
+#[derive(Debug)]
 pub struct UploadManager {
-    destination: PathBuf,
+    /// Local directory where archives are written before being handed to the storage.
+    staging: PathBuf,
+    storage: Arc<dyn StorageBackend>,
     max_size: usize,
+    default_expiry: Option<Duration>,
+    compression: CompressionConfiguration,
+    quotas: QuotaConfiguration,
+    reservations: Arc<Reservations>,
+    /// Whether new uploads are stored as deduplicated blobs rather than as one archive each.
+    dedupe: bool,
+    blobs: Arc<BlobStore>,
+    index: Arc<UploadIndex>,
 }
 
-impl UploadManager {
-    pub fn new(dst: PathBuf, max_size: usize) -> Self {
-        UploadManager { destination: dst, max_size }
+impl UploadManager {
+    pub fn new(staging: PathBuf, storage: Arc<dyn StorageBackend>, index: UploadIndex, configs: &UploadConfiguration) -> Self {
+        let index = Arc::new(index);
+
+        UploadManager {
+            staging,
+            blobs: Arc::new(BlobStore::new(storage.clone(), index.clone())),
+            storage,
+            max_size: configs.max_size(),
+            default_expiry: configs.default_expiry(),
+            compression: configs.compression().clone(),
+            quotas: configs.quotas().clone(),
+            reservations: Arc::default(),
+            dedupe: configs.dedupe(),
+            index,
+        }
+    }
+
+    pub fn from_configs(configs: &UploadConfiguration) -> Result<Self, AppError> {
+        let (index, is_new) = UploadIndex::open(configs.index_file(), configs.state_file())?;
+
+        let staging = configs.upload_directory().join(".staging");
+
+        std::fs::create_dir_all(&staging)
+            .map_err(|e| AppError::InitError(format!("Error creating staging directory: {}", e)))?;
+
+        let manager = UploadManager::new(
+            staging,
+            get_storage(configs)?,
+            index,
+            configs,
+        );
+
+        if is_new {
+            let indexed = manager.rebuild_index()?;
+
+            info!("Created upload index {} with {} uploads", configs.index_file().display(), indexed);
+        }
+
+        Ok(manager)
+    }
+
+    pub fn index(&self) -> Arc<UploadIndex> {
+        self.index.clone()
+    }
+
+    fn staging_path(&self, link: &str) -> PathBuf {
+        self.staging.join(archive_key(link))
+    }
+
+    /// Directory where the files of a deduplicated upload are hashed before being stored as blobs.
+    fn blob_staging_path(&self, link: &str) -> PathBuf {
+        self.staging.join(link)
+    }
+
+    /// Opens the archive of a link, checking `key` when the upload is protected.
+    pub fn get_file_from_link<F: AsRef<str>>(&self, link: F, key: LinkKey) -> Result<Download, AppError> {
+        let record = self.authorize(link.as_ref(), key)?;
+
+        if let Some(ref blobs) = record.metadata.blobs {
+            let stream = ZipStream::new(self.storage.clone(), &zip_entries(blobs, BlobStore::key), record.metadata.created);
+
+            let content = DownloadContent::Archive {
+                size: stream.size(),
+                reader: Box::new(stream),
+                name: archive_key(link.as_ref()),
+            };
+
+            let sha256 = record.metadata.sha256.clone();
+
+            return self.start_download(record, content).map(|download| Download { sha256, ..download });
+        }
+
+        let key = archive_key(link.as_ref());
+        let sha256 = record.metadata.sha256.clone();
+
+        let content = match self.storage.local_path(&key) {
+            Some(path) => {
+                let file = NamedFile::open(path)
+                    .map_err(|e| {
+                        error!("Error serving file: {:?}", e);
+
+                        AppError::FileNotFound
+                    })?
+                    // The checksum replaces the modification time based etag, for archives uploaded since it exists.
+                    .use_etag(sha256.is_none());
+
+                DownloadContent::File { file: Box::new(file), name: key }
+            }
+            None => DownloadContent::Archive { reader: self.storage.get(&key)?, name: key, size: record.size },
+        };
+
+        self.start_download(record, content).map(|download| Download { sha256, ..download })
+    }
+
+    /// Opens a single file of the archive of a link, checking `key` when the upload is protected.
+    pub fn get_entry_from_link(&self, link: &str, name: &str, key: LinkKey) -> Result<Download, AppError> {
+        let record = self.authorize(link, key)?;
+
+        let (size, sha256) = record.files
+            .iter()
+            .find(|file| file.name == name)
+            .map(|file| (file.size, file.sha256.clone()))
+            .ok_or(AppError::FileNotFound)?;
+
+        let blob = record.metadata.blobs
+            .iter()
+            .flatten()
+            .find(|blob| blob.name == name)
+            .map(|blob| BlobStore::key(&blob.sha256));
+
+        if let Some(key) = blob {
+            let content = match self.storage.local_path(&key) {
+                Some(path) => {
+                    let (content_type, disposition) = content_headers(name);
+
+                    let file = NamedFile::open(path)
+                        .map_err(|e| {
+                            error!("Error serving file: {:?}", e);
+
+                            AppError::FileNotFound
+                        })?
+                        .set_content_type(content_type)
+                        .set_content_disposition(disposition)
+                        .use_etag(false);
+
+                    DownloadContent::File { file: Box::new(file), name: name.to_string() }
+                }
+                None => DownloadContent::Archive { reader: self.storage.get(&key)?, name: name.to_string(), size },
+            };
+
+            return self.start_download(record, content).map(|download| Download { sha256, ..download });
+        }
+
+        let archive = self.storage.open(&archive_key(link)).map_err(|e| {
+            error!("Error serving file: {:?}", e);
+
+            AppError::FileNotFound
+        })?;
+
+        self.start_download(record, DownloadContent::Entry { archive, name: name.to_string(), size })
+            .map(|download| Download { sha256, ..download })
+    }
+
+    /// Download of already opened content, to `count` when the upload has a download limit.
+    fn start_download(&self, record: UploadRecord, content: DownloadContent) -> Result<Download, AppError> {
+        let counter = record.metadata.max_downloads.map(|_| DownloadCounter {
+            index: self.index.clone(),
+            storage: self.storage.clone(),
+            blobs: self.blobs.clone(),
+            record,
+        });
+
+        Ok(Download { content, counter, counted: None, sha256: None })
+    }
+
+    /// Looks up a link, refusing it when expired or when `key` does not open it.
+    ///
+    /// Checking a password verifies a slow hash: call it from `web::block`.
+    pub fn authorize(&self, link: &str, key: LinkKey) -> Result<UploadRecord, AppError> {
+        if !is_link_valid(link) {
+            debug!("invalid link: {}", link);
+            return Err(AppError::FileNotFound);
+        }
+
+        let record = self.index.get(link)?.ok_or(AppError::FileNotFound)?;
+
+        if record.metadata.is_expired() || record.metadata.remaining_downloads() == Some(0) {
+            debug!("expired link: {}", link);
+            return Err(AppError::Gone);
+        }
+
+        if let Some(ref hash) = record.metadata.password_hash {
+            match key {
+                LinkKey::Nothing => return Err(AppError::PasswordRequired),
+                LinkKey::Password(ref password) if !verify_password(password, hash) => {
+                    debug!("wrong password for link: {}", link);
+                    return Err(AppError::InvalidPassword);
+                }
+                _ => {}
+            }
+        }
+
+        Ok(record)
     }
 
-    pub fn get_file_from_link<F: AsRef<str>>(&self, link: F) -> Result<NamedFile, AppError> {
-        if !is_link_valid(&link) {
-            debug!("invalid link: {}", link.as_ref());
-            return Err(AppError::FileNotFound);
-        }
+    pub async fn store(&self, payload: Multipart, uploader: Option<String>) -> Result<String, AppError> {
+        let limits = UploadLimits {
+            quota: self.reserve(uploader.as_deref(), 0)?,
+            ..UploadLimits::default()
+        };
+
+        self.store_upload(payload, limits, |fields| UploadOptions::from_fields(fields).map(|options| options.with_uploader(uploader)))
+            .await
+            .map(|record| record.link)
+    }
 
-        let file_to_serve = self.destination.join(format!("{}.zip", link.as_ref()));
+    /// Stores an upload sent through an upload request, on behalf of its creator and within what is left of the
+    /// request's limits.
+    pub async fn store_requested(&self, payload: Multipart, token: &str) -> Result<String, AppError> {
+        let request = self.get_request(token)?;
 
-        Ok(NamedFile::open(file_to_serve).map_err(|e| {
-            error!("Error serving file: {:?}", e);
+        let limits = UploadLimits {
+            quota: self.reserve(Some(&request.creator), 0)?,
+            max_size: request.remaining_size(),
+            max_files: request.remaining_files(),
+        };
 
-            AppError::FileNotFound
-        })?)
+        let record = self.store_upload(payload, limits, |fields| UploadOptions::requested(fields, &request)).await?;
+
+        let size = record.files.iter().map(|file| file.size).sum();
+
+        // Uploads sent at the same time were each checked against the same remaining limits.
+        if !self.index.record_request_upload(&request.token, record.files.len() as u64, size)? {
+            self.remove(&record.link)?;
+
+            return Err(AppError::PayloadTooLarge("The upload request received all the files it accepts".to_string()));
+        }
+
+        Ok(record.link)
     }
 
-    pub async fn store(&self, mut payload: Multipart) -> Result<String, AppError> {
+    async fn store_upload<F>(&self, payload: Multipart, mut limits: UploadLimits, options: F) -> Result<UploadRecord, AppError>
+        where F: FnOnce(&HashMap<String, String>) -> Result<UploadOptions, AppError> {
         let archive_name = generate_random_link();
-        let dest_path = self.destination.clone();
 
+        // The reservation in `limits` lives until the upload is indexed, or discarded.
+        let result = match self.write_archive(payload, &archive_name, &mut limits).await {
+            Ok((fields, written)) => match options(&fields) {
+                Ok(options) => self.register(&archive_name, &options, written).await,
+                Err(err) => Err(err),
+            },
+            Err(err) => Err(err),
+        };
+
+        if result.is_err() {
+            self.discard(&archive_name).await;
+        }
+
+        result
+    }
+
+    /// Streams every file of the form into the archive, or into blobs when deduplicating, returning the
+    /// remaining plain form fields and the blobs. `limits` are checked as files and bytes arrive.
+    async fn write_archive(&self, mut payload: Multipart, link: &str, limits: &mut UploadLimits) -> Result<(HashMap<String, String>, WrittenFiles), AppError> {
         let mut uploaded: usize = 0;
 
-        let max_size = self.max_size;
+        let mut file_count: u64 = 0;
 
-        let filename_0 = format!("{}/{}.zip", dest_path.to_string_lossy(), archive_name);
-        let filename_1 = filename_0.clone();
-        let filename_2 = filename_0.clone();
+        let max_size = self.max_size;
 
         let mut is_empty = true;
 
-        let target = web::block(move || std::fs::File::create(filename_0)).await?;
+        let mut fields = HashMap::new();
 
-        let mut zipper = zip::ZipWriter::new(target);
+        let mut paths = ArchivePaths::new();
 
-        let options =
-            zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        let (dedupe, archive_path, blob_directory) = (self.dedupe, self.staging_path(link), self.blob_staging_path(link));
+
+        let mut target = web::block(move || UploadTarget::create(dedupe, archive_path, blob_directory)).await?;
 
         while let Ok(Some(mut field)) = payload.try_next().await {
-            let some_name = field
-                .content_disposition()
-                .map(|d| d.get_filename().map(|s| s.to_string()))
-                .flatten();
+            let disposition = field.content_disposition();
+
+            let some_name = disposition.as_ref()
+                .and_then(|d| d.get_filename().map(|filename| relative_path(d.get_name(), filename)));
+
+            let filename = match some_name {
+                Some(path) => {
+                    let (directories, filename) = paths.add(&sanitize_path(&path)?);
+
+                    for directory in directories {
+                        target.add_directory(directory)?;
+                    }
+
+                    filename
+                }
+                None => {
+                    let name = field
+                        .content_disposition()
+                        .and_then(|d| d.get_name().map(|s| s.to_string()))
+                        .unwrap_or_default();
+
+                    let mut value = Vec::new();
+
+                    while let Some(chunk) = field.next().await {
+                        value.extend_from_slice(&chunk?);
+
+                        if value.len() > MAX_FIELD_SIZE {
+                            return Err(AppError::InvalidRequest(format!("Field {} is too big", name)));
+                        }
+                    }
+
+                    fields.insert(name, String::from_utf8_lossy(&value).to_string());
 
-            let filename = some_name.unwrap_or(generate_random_link());
+                    continue;
+                }
+            };
+
+            let options = self.archive_options(&[field.content_type(), &mime_guess::from_path(&filename).first_or_octet_stream()]);
+
+            let expected = expected_digest(field.headers())?;
 
-            zipper.start_file(filename, options)?;
+            file_count += 1;
+
+            if let Some(max_files) = limits.max_files.filter(|max_files| file_count > *max_files) {
+                return Err(AppError::PayloadTooLarge(format!("At most {} more files can be uploaded", max_files)));
+            }
+
+            target.start_file(filename.clone(), options)?;
 
             while let Some(chunk) = field.next().await {
                 let data = chunk?;
@@ -101,71 +1250,449 @@ impl UploadManager {
                 uploaded += data.len();
 
                 if uploaded > max_size {
-                    if let Err(err) = web::block(move || std::fs::remove_file(filename_1)).await {
-                        error!("Cannot delete file: {}: {:?}", filename_2, err);
-                    }
+                    return Err(AppError::ArchiveError("File to big".to_string()));
+                }
+
+                if let Some(max_size) = limits.max_size.filter(|max_size| uploaded as u64 > *max_size) {
+                    return Err(AppError::PayloadTooLarge(format!("At most {} more bytes can be uploaded", max_size)));
+                }
 
-                    return Err(AppError::ArchiveError(format!("File to big")));
+                if let Some(ref mut quota) = limits.quota {
+                    quota.add(data.len() as u64)?;
                 }
 
-                zipper = web::block(move || zipper.write_all(data.bytes()).map(|_| zipper)).await?;
+                target = web::block(move || target.write_all(data.bytes()).map(|_| target)).await?;
 
                 is_empty = false;
             }
+
+            let sha256 = target.end_file()?;
+
+            if expected.is_some_and(|expected| expected != sha256) {
+                return Err(AppError::InvalidRequest(format!("Checksum mismatch for {}", filename)));
+            }
         }
 
         if is_empty {
-            if let Err(err) = web::block(move || std::fs::remove_file(filename_1)).await {
-                error!("Cannot delete file: {}: {:?}", filename_2, err);
+            return Err(AppError::ArchiveError("Empty file".to_string()));
+        }
+
+        let written = web::block(move || target.finish()).await?;
+
+        Ok((fields, written))
+    }
+
+    /// Archives an already staged file, as done for finished resumable uploads.
+    pub async fn store_file(&self, source: PathBuf, filename: String, options: UploadOptions) -> Result<String, AppError> {
+        let archive_name = generate_random_link();
+        let (dedupe, archive_path, blob_directory) = (self.dedupe, self.staging_path(&archive_name), self.blob_staging_path(&archive_name));
+        let file_options = self.archive_options(&[&mime_guess::from_path(&filename).first_or_octet_stream()]);
+        let filename = ArchivePaths::new().add(&filename).1;
+
+        let result = web::block(move || -> Result<WrittenFiles, AppError> {
+            let mut target = UploadTarget::create(dedupe, archive_path, blob_directory)?;
+
+            target.start_file(filename, file_options)?;
+
+            std::io::copy(&mut std::fs::File::open(source)?, &mut target)?;
+
+            target.end_file()?;
+            target.finish()
+        }).await;
+
+        let result = match result {
+            Ok(written) => self.register(&archive_name, &options, written).await,
+            Err(err) => Err(err.into()),
+        };
+
+        if let Err(err) = result {
+            self.discard(&archive_name).await;
+
+            return Err(err);
+        }
+
+        Ok(archive_name)
+    }
+
+    /// Moves a finished archive from the staging directory to the storage, with its metadata file, and indexes it.
+    async fn register(&self, link: &str, options: &UploadOptions, written: WrittenFiles) -> Result<UploadRecord, AppError> {
+        let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?;
+
+        let expires_at = options.expires_in
+            .unwrap_or(self.default_expiry)
+            .map(|expires_in| (now + expires_in).as_secs());
+
+        let mut metadata = UploadMetadata {
+            created: now.as_secs(),
+            expires_at,
+            max_downloads: options.max_downloads,
+            uploader: options.uploader.clone(),
+            message: options.message.clone(),
+            label: options.label.clone(),
+            // Blobs already carry the checksums of deduplicated uploads.
+            checksums: if written.blobs.is_some() { BTreeMap::new() } else { written.checksums },
+            blobs: written.blobs,
+            ..UploadMetadata::default()
+        };
+
+        let storage = self.storage.clone();
+        let index = self.index.clone();
+        let blob_store = self.blobs.clone();
+        let blob_staging = self.blob_staging_path(link);
+        let staged = self.staging_path(link);
+        let link = link.to_string();
+        let password = options.password.clone();
+
+        web::block(move || -> Result<UploadRecord, AppError> {
+            if let Some(password) = password {
+                metadata.password_hash = Some(hash_password(&password)?);
+            }
+
+            if let Some(ref blobs) = metadata.blobs {
+                // Blobs are staged as `<blob_staging>/<sha256>` until stored.
+                let entries = zip_entries(blobs, |sha256| sha256.to_string());
+                let stream = ZipStream::new(Arc::new(LocalStorage::new(blob_staging.clone())), &entries, metadata.created);
+
+                metadata.sha256 = Some(sha256_of(stream)?);
+
+                let record = blob_record(&link, metadata);
+
+                blob_store.register(&blob_staging, &record)?;
+
+                std::fs::remove_dir_all(&blob_staging)?;
+
+                return Ok(record);
+            }
+
+            let size = staged.metadata()?.len();
+
+            let files = with_checksums(Self::read_files(std::fs::File::open(&staged)?)?, &metadata.checksums);
+
+            metadata.sha256 = Some(sha256_of(std::fs::File::open(&staged)?)?);
+
+            storage.put_file(&archive_key(&link), &staged)?;
+
+            metadata.write(storage.as_ref(), &link)?;
+
+            let record = UploadRecord { link, size, metadata, files };
+
+            index.insert(&record)?;
+
+            Ok(record)
+        }).await.map_err(AppError::from)
+    }
+
+    /// Storage used by `uploader` and the quota that applies to them.
+    pub fn quota(&self, uploader: &str) -> Result<Quota, AppError> {
+        let used = self.index.usage(uploader)?;
+        let limit = self.quotas.quota(uploader);
+
+        Ok(Quota { used, limit, remaining: limit.map(|limit| limit.saturating_sub(used)) })
+    }
+
+    /// Reserves `size` bytes of the quota of `uploader`, `None` for anonymous uploads and unlimited quotas.
+    pub fn reserve(&self, uploader: Option<&str>, size: u64) -> Result<Option<QuotaReservation>, AppError> {
+        let (uploader, limit) = match uploader.and_then(|uploader| self.quotas.quota(uploader).map(|limit| (uploader, limit))) {
+            Some(quota) => quota,
+            None => return Ok(None),
+        };
+
+        let mut reservation = QuotaReservation {
+            index: self.index.clone(),
+            reservations: self.reservations.clone(),
+            uploader: uploader.to_string(),
+            limit,
+            reserved: 0,
+        };
+
+        reservation.add(size)?;
+
+        Ok(Some(reservation))
+    }
+
+    /// Removes whatever was written for a failed upload.
+    async fn discard(&self, link: &str) {
+        let staged = self.staging_path(link);
+        let blob_staging = self.blob_staging_path(link);
+        let storage = self.storage.clone();
+        let link = link.to_string();
+
+        let result = web::block(move || -> Result<(), AppError> {
+            if staged.exists() {
+                std::fs::remove_file(staged)?;
+            }
+
+            if blob_staging.exists() {
+                std::fs::remove_dir_all(blob_staging)?;
             }
 
-            return Err(AppError::ArchiveError(format!("Empty file")));
+            remove_objects(storage.as_ref(), &link)
+        }).await;
+
+        if let Err(err) = result {
+            error!("Cannot delete failed upload: {:?}", err);
         }
+    }
 
-        zipper.finish()?;
+    /// Deletes every upload past its expiry, returning the removed links and the bytes reclaimed.
+    pub fn remove_expired(&self) -> Result<Vec<(String, u64)>, AppError> {
+        let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?.as_secs();
 
-        Ok(archive_name)
+        let mut removed = Vec::new();
+
+        for record in self.index.expired(now)? {
+            self.remove(&record.link)?;
+
+            removed.push((record.link, record.size));
+        }
+
+        let requests = self.index.remove_expired_requests(now)?;
+
+        if requests > 0 {
+            info!("Removed {} expired upload requests", requests);
+        }
+
+        Ok(removed)
+    }
+
+    /// Creates a link letting anyone upload files on behalf of `creator`.
+    pub fn create_request(&self, creator: &str, options: RequestOptions) -> Result<UploadRequest, AppError> {
+        if options.label.as_ref().is_some_and(|label| label.chars().count() > MAX_LABEL_LENGTH) {
+            return Err(AppError::InvalidRequest(format!("Labels are limited to {} characters", MAX_LABEL_LENGTH)));
+        }
+
+        let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?;
+
+        let request = UploadRequest {
+            token: generate_random_link(),
+            creator: creator.to_string(),
+            created: now.as_secs(),
+            expires_at: options.expires_in.map(|expires_in| (now + expires_in).as_secs()),
+            max_size: options.max_size,
+            max_files: options.max_files,
+            label: options.label,
+            uploads: 0,
+            files: 0,
+            size: 0,
+        };
+
+        self.index.insert_request(&request)?;
+
+        Ok(request)
+    }
+
+    /// Looks up an upload request, refusing it once expired.
+    pub fn get_request(&self, token: &str) -> Result<UploadRequest, AppError> {
+        if !is_link_valid(token) {
+            return Err(AppError::FileNotFound);
+        }
+
+        let request = self.index.get_request(token)?.ok_or(AppError::FileNotFound)?;
+
+        if request.is_expired(SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?.as_secs()) {
+            return Err(AppError::Gone);
+        }
+
+        Ok(request)
+    }
+
+    /// Upload requests created by `creator`, newest first.
+    pub fn list_requests(&self, creator: &str) -> Result<Vec<UploadRequest>, AppError> {
+        self.index.list_requests(creator)
+    }
+
+    /// Deletes an upload request of `creator`, the uploads it received are kept.
+    pub fn delete_request(&self, token: &str, creator: &str) -> Result<(), AppError> {
+        if !is_link_valid(token) || !self.index.remove_request(token, creator)? {
+            return Err(AppError::FileNotFound);
+        }
+
+        Ok(())
+    }
+
+    /// Deletes the archive, metadata file and index entry of an upload.
+    fn remove(&self, link: &str) -> Result<(), AppError> {
+        let released = self.index.remove(link)?;
+
+        Cleanup { storage: self.storage.clone(), blobs: self.blobs.clone(), link: link.to_string(), released }.run()
+    }
+
+    /// Options for a file of the archive, given the content types it was sent as or guessed from its name.
+    fn archive_options(&self, content_types: &[&mime::Mime]) -> zip::write::FileOptions {
+        let options = zip::write::FileOptions::default();
+
+        if self.compression.skip_compressed() && content_types.iter().any(|content_type| is_compressed(content_type)) {
+            return options.compression_method(zip::CompressionMethod::Stored);
+        }
+
+        options
+            .compression_method(self.compression.method())
+            .compression_level(self.compression.level())
+    }
+
+    /// Looks up a link without the checks done for downloads, as the owner sees it.
+    pub fn get_upload(&self, link: &str) -> Result<UploadRecord, AppError> {
+        if !is_link_valid(link) {
+            return Err(AppError::FileNotFound);
+        }
+
+        self.index.get(link)?.ok_or(AppError::FileNotFound)
+    }
+
+    /// Every upload, or only those of `uploader`, newest first.
+    pub fn list_uploads(&self, uploader: Option<&str>) -> Result<Vec<UploadRecord>, AppError> {
+        self.index.list(uploader)
+    }
+
+    /// Looks up an upload `user` may see and change: one of theirs. Admins may change any, including those sent
+    /// without an account.
+    pub fn owned_upload(&self, link: &str, user: Option<&AuthenticatedUser>) -> Result<UploadRecord, AppError> {
+        let record = self.get_upload(link)?;
+
+        match (user, record.metadata.uploader.as_ref()) {
+            (Some(user), _) if user.is_admin() => Ok(record),
+            (Some(user), Some(uploader)) if &user.name == uploader => Ok(record),
+            _ => Err(AppError::FileNotFound),
+        }
+    }
+
+    /// Deletes an upload of `user` before its expiry.
+    pub fn delete(&self, link: &str, user: Option<&AuthenticatedUser>) -> Result<(), AppError> {
+        let record = self.owned_upload(link, user)?;
+
+        self.remove(&record.link)
+    }
+
+    /// Sets the expiry of an upload of `user` to `expires_in` from now, `None` to keep it forever.
+    pub fn set_expiry(&self, link: &str, expires_in: Option<Duration>, user: Option<&AuthenticatedUser>) -> Result<UploadRecord, AppError> {
+        let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?;
+
+        self.update_metadata(link, user, |metadata| {
+            metadata.expires_at = expires_in.map(|expires_in| (now + expires_in).as_secs());
+        })
+    }
+
+    /// Names an upload of `user` in the admin area, `None` to remove the name.
+    pub fn set_label(&self, link: &str, label: Option<String>, user: Option<&AuthenticatedUser>) -> Result<UploadRecord, AppError> {
+        if label.as_ref().map(|label| label.chars().count() > MAX_LABEL_LENGTH).unwrap_or(false) {
+            return Err(AppError::InvalidRequest(format!("Labels are limited to {} characters", MAX_LABEL_LENGTH)));
+        }
+
+        self.update_metadata(link, user, |metadata| metadata.label = label)
+    }
+
+    /// Changes the metadata of an upload of `user`, in its metadata file and in the index.
+    fn update_metadata<F: FnOnce(&mut UploadMetadata)>(&self, link: &str, user: Option<&AuthenticatedUser>, change: F) -> Result<UploadRecord, AppError> {
+        let mut record = self.owned_upload(link, user)?;
+
+        change(&mut record.metadata);
+
+        record.metadata.write(self.storage.as_ref(), link)?;
+
+        self.index.update_metadata(link, &record.metadata)?;
+
+        Ok(record)
     }
 
-    pub fn list_directory(&self) -> Result<Vec<FileInfo>, AppError> {
+    /// Files of every upload, or only of those uploaded by `uploader`.
+    pub fn list_directory(&self, uploader: Option<&str>) -> Result<Vec<FileInfo>, AppError> {
         let mut dirs = Vec::new();
-        let dir_entries = std::fs::read_dir(&self.destination)?;
-
-        for entry_result in dir_entries {
-            let entry = entry_result?;
-
-            let name = entry.file_name().to_string_lossy().to_string();
-            let size = entry.metadata()?.len();
-            let created = entry
-                .metadata()?
-                .created()?
-                .duration_since(SystemTime::UNIX_EPOCH)?;
-
-            if name.ends_with("zip") {
-                let result_archive = zip::ZipArchive::new(std::fs::File::open(entry.path())?);
-
-                if let Ok(archive) = result_archive {
-                    for file in archive.file_names() {
-                        dirs.push(FileInfo::new(
-                            file.to_string(),
-                            FileType::Archive(name.replace(".zip", "").to_string()),
-                            size,
-                            created.as_secs(),
-                        ))
-                    }
-                }
-            } else {
-                dirs.push(FileInfo::new(
-                    name.to_string(),
-                    FileType::Regular,
-                    size,
-                    created.as_secs(),
-                ))
+
+        for record in self.index.list(uploader)? {
+            for file in record.files.iter().cloned() {
+                dirs.push(FileInfo::new(&record, file))
             }
         }
 
         Ok(dirs)
     }
+
+    pub fn uploaders(&self) -> Result<Vec<String>, AppError> {
+        self.index.uploaders()
+    }
+
+    /// Names and sizes of the files of an archive.
+    fn read_files<R: Read + Seek>(archive: R) -> Result<Vec<IndexedFile>, AppError> {
+        let mut archive = zip::ZipArchive::new(archive)?;
+
+        let mut files = Vec::with_capacity(archive.len());
+
+        for i in 0..archive.len() {
+            let file = archive.by_index(i)?;
+
+            if file.is_dir() || file.name() == CHECKSUMS_FILE {
+                continue;
+            }
+
+            files.push(IndexedFile { name: file.name().to_string(), size: file.size(), sha256: None });
+        }
+
+        Ok(files)
+    }
+
+    /// Reads the archive and metadata of a stored upload into an index record.
+    fn read_record(&self, link: &str, size: u64, metadata: UploadMetadata) -> Result<UploadRecord, AppError> {
+        let files = with_checksums(Self::read_files(self.storage.open(&archive_key(link))?)?, &metadata.checksums);
+
+        Ok(UploadRecord { link: link.to_string(), size, metadata, files })
+    }
+
+    /// Recreates the index from the archives and metadata files in the storage.
+    pub fn rebuild_index(&self) -> Result<usize, AppError> {
+        self.index.clear()?;
+
+        let mut indexed = 0;
+
+        let names = self.storage.list()?;
+
+        for name in &names {
+            // Deduplicated uploads only have a metadata file, listing their blobs.
+            let link = match name.strip_suffix(".json") {
+                Some(link) if is_link_valid(link) && !names.contains(&archive_key(link)) => link,
+                _ => continue,
+            };
+
+            match UploadMetadata::read(self.storage.as_ref(), link)? {
+                Some(metadata) if metadata.blobs.is_some() => {
+                    self.index.insert(&blob_record(link, metadata))?;
+
+                    indexed += 1;
+                }
+                _ => warn!("Skipping metadata without archive {}", name),
+            }
+        }
+
+        for name in names {
+            let link = match name.strip_suffix(".zip") {
+                Some(link) if is_link_valid(link) => link.to_string(),
+                _ => continue,
+            };
+
+            let info = match self.storage.stat(&name)? {
+                Some(info) => info,
+                None => continue,
+            };
+
+            let metadata = match UploadMetadata::read(self.storage.as_ref(), &link)? {
+                Some(metadata) => metadata,
+                None => UploadMetadata {
+                    created: info.modified.duration_since(SystemTime::UNIX_EPOCH)?.as_secs(),
+                    ..UploadMetadata::default()
+                },
+            };
+
+            match self.read_record(&link, info.size, metadata) {
+                Ok(record) => {
+                    self.index.insert(&record)?;
+
+                    indexed += 1;
+                }
+                Err(e) => warn!("Skipping unreadable archive {}: {}", name, e),
+            }
+        }
+
+        Ok(indexed)
+    }
 }
 
 #[derive(Serialize, Clone)]
@@ -175,13 +1702,13 @@ pub struct DisplayDirectories {
 }
 
 impl DisplayDirectories {
-    pub fn from(data: &Vec<FileInfo>) -> Vec<Self> {
+    pub fn from(data: &[FileInfo]) -> Vec<Self> {
         let mut result = Vec::new();
 
         let data = data
             .iter()
             .map(|f| {
-                let date = chrono::NaiveDateTime::from_timestamp(f.created as i64, 0);
+                let date = chrono::DateTime::from_timestamp(f.created as i64, 0).unwrap_or_default();
 
                 (date.format("%Y, %m %d").to_string(), f)
             })
@@ -200,12 +1727,9 @@ impl DisplayDirectories {
             };
 
             for entry in entries {
-                let key = match entry.file_type {
-                    FileType::Archive(ref name) => name,
-                    FileType::Regular => &entry.name,
-                };
+                let FileType::Archive(ref key) = entry.file_type;
 
-                let fs = d.files.entry(key.to_string()).or_insert(Vec::new());
+                let fs = d.files.entry(key.to_string()).or_default();
 
                 fs.push(entry.clone());
             }
@@ -224,3 +1748,179 @@ impl DisplayDirectories {
         result
     }
 }
+
+#[test]
+fn is_compressed_works() {
+    let compressed = |name: &str| is_compressed(&mime_guess::from_path(name).first_or_octet_stream());
+
+    assert!(compressed("photo.jpg"));
+    assert!(compressed("movie.mp4"));
+    assert!(compressed("backup.zip"));
+    assert!(compressed("report.docx"));
+    assert!(!compressed("drawing.svg"));
+    assert!(!compressed("notes.txt"));
+    assert!(!compressed("data.bin"));
+}
+
+#[test]
+fn archive_paths_works() {
+    assert_eq!(sanitize_path("photos/./2023//a.jpg").unwrap(), "photos/2023/a.jpg");
+    assert!(sanitize_path("../etc/passwd").is_err());
+    assert!(sanitize_path("photos/../../a.jpg").is_err());
+    assert!(sanitize_path("/etc/passwd").is_err());
+    assert!(sanitize_path("C:\\Windows\\a.jpg").is_err());
+
+    assert_eq!(relative_path(Some("photos/2023/a.jpg"), "a.jpg"), "photos/2023/a.jpg");
+    assert_eq!(relative_path(Some("file"), "a.jpg"), "a.jpg");
+
+    let mut paths = ArchivePaths::new();
+
+    assert_eq!(paths.add("SHA256SUMS").1, "SHA256SUMS (1)");
+    assert_eq!(paths.add("photos/2023/a.jpg"), (vec!["photos/".to_string(), "photos/2023/".to_string()], "photos/2023/a.jpg".to_string()));
+    assert_eq!(paths.add("photos/2024/a.jpg"), (vec!["photos/2024/".to_string()], "photos/2024/a.jpg".to_string()));
+    assert_eq!(paths.add("photos/2023/a.jpg").1, "photos/2023/a (1).jpg");
+    assert_eq!(paths.add("photos/2023/a.jpg").1, "photos/2023/a (2).jpg");
+    assert_eq!(paths.add("photos").1, "photos (1)");
+}
+
+#[cfg(test)]
+fn test_manager(section: &str) -> (UploadManager, PathBuf) {
+    let directory = std::env::temp_dir().join(format!("fshare-{}", generate_random_link()));
+    let configs = ini::Ini::load_from_str(&format!("[upload]\nupload_directory={}\n{}", directory.display(), section)).unwrap();
+    let configs = UploadConfiguration::try_from(&configs).unwrap();
+    let (index, _) = UploadIndex::open(Path::new(":memory:"), Path::new(":memory:")).unwrap();
+
+    (UploadManager::new(directory.join(".staging"), get_storage(&configs).unwrap(), index, &configs), directory)
+}
+
+#[test]
+fn uploads_are_changed_by_their_owner() {
+    use crate::auth_middleware::Role;
+
+    let (manager, directory) = test_manager("");
+
+    for (link, uploader) in [("abcdefghij", Some("alice")), ("klmnopqrst", None)] {
+        manager.index.insert(&UploadRecord {
+            link: link.to_string(),
+            size: 10,
+            metadata: UploadMetadata { created: 1, uploader: uploader.map(|uploader| uploader.to_string()), ..UploadMetadata::default() },
+            files: vec![],
+        }).unwrap();
+    }
+
+    let user = |name: &str, role: Role| AuthenticatedUser::new(name.to_string(), role);
+    let (alice, bob, root) = (user("alice", Role::User), user("bob", Role::User), user("root", Role::Admin));
+
+    assert!(matches!(manager.delete("abcdefghij", Some(&bob)), Err(AppError::FileNotFound)));
+    assert!(matches!(manager.delete("abcdefghij", None), Err(AppError::FileNotFound)));
+    assert!(matches!(manager.set_label("abcdefghij", Some("mine".to_string()), Some(&bob)), Err(AppError::FileNotFound)));
+    assert!(matches!(manager.set_expiry("abcdefghij", None, Some(&bob)), Err(AppError::FileNotFound)));
+    assert!(manager.get_upload("abcdefghij").unwrap().metadata.label.is_none());
+
+    assert_eq!(manager.set_label("abcdefghij", Some("mine".to_string()), Some(&alice)).unwrap().metadata.label.as_deref(), Some("mine"));
+    assert_eq!(manager.set_label("abcdefghij", None, Some(&root)).unwrap().metadata.label, None);
+    manager.delete("abcdefghij", Some(&alice)).unwrap();
+
+    // Uploads sent without an account belong to no one but the admins.
+    assert!(matches!(manager.delete("klmnopqrst", Some(&bob)), Err(AppError::FileNotFound)));
+    assert!(matches!(manager.delete("klmnopqrst", None), Err(AppError::FileNotFound)));
+    assert!(matches!(manager.set_expiry("klmnopqrst", None, Some(&alice)), Err(AppError::FileNotFound)));
+    manager.delete("klmnopqrst", Some(&root)).unwrap();
+
+    assert!(manager.list_uploads(None).unwrap().is_empty());
+
+    std::fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn quota_reservations_work() {
+    let (manager, directory) = test_manager("quota=2M");
+
+    manager.index.insert(&UploadRecord {
+        link: "abcdefghij".to_string(),
+        size: 10,
+        metadata: UploadMetadata { created: 1, uploader: Some("alice".to_string()), ..UploadMetadata::default() },
+        files: vec![IndexedFile { name: "a.txt".to_string(), size: 1024 * 1024, sha256: None }],
+    }).unwrap();
+
+    assert!(manager.reserve(None, 1 << 30).unwrap().is_none());
+
+    let mut first = manager.reserve(Some("alice"), 0).unwrap().unwrap();
+
+    first.add(512 * 1024).unwrap();
+
+    // Both uploads fit in what is left on their own, not together.
+    assert!(matches!(manager.reserve(Some("alice"), 768 * 1024), Err(AppError::QuotaExceeded(_))));
+    assert!(manager.reserve(Some("bob"), 768 * 1024).unwrap().is_some());
+
+    first.add(256 * 1024).unwrap();
+    assert!(matches!(first.add(256 * 1024 + 1), Err(AppError::QuotaExceeded(_))));
+
+    drop(first);
+
+    assert!(manager.reserve(Some("alice"), 768 * 1024).unwrap().is_some());
+
+    std::fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn one_time_links_are_downloaded_once() {
+    let (manager, directory) = test_manager("");
+    let manager = Arc::new(manager);
+
+    manager.storage.put(&archive_key("abcdefghij"), &mut &b"content"[..], 7).unwrap();
+
+    manager.index.insert(&UploadRecord {
+        link: "abcdefghij".to_string(),
+        size: 7,
+        metadata: UploadMetadata { created: 1, max_downloads: Some(1), ..UploadMetadata::default() },
+        files: vec![],
+    }).unwrap();
+
+    let barrier = Arc::new(std::sync::Barrier::new(8));
+
+    let downloads = (0..8)
+        .map(|_| {
+            let (manager, barrier) = (manager.clone(), barrier.clone());
+
+            std::thread::spawn(move || {
+                barrier.wait();
+
+                manager.get_file_from_link("abcdefghij", LinkKey::Nothing).and_then(|mut download| download.count())
+            })
+        })
+        .collect::<Vec<_>>()
+        .into_iter()
+        .map(|thread| thread.join().unwrap())
+        .collect::<Vec<_>>();
+
+    assert_eq!(downloads.iter().filter(|download| download.is_ok()).count(), 1);
+    assert!(downloads.iter().all(|download| matches!(download, Ok(()) | Err(AppError::Gone))));
+    assert!(matches!(manager.get_file_from_link("abcdefghij", LinkKey::Nothing), Err(AppError::Gone)));
+
+    std::fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn conditions_use_the_checksum_etag() {
+    use actix_web::test::TestRequest;
+
+    let condition = |headers: &[(&str, &str)]| {
+        let request = headers
+            .iter()
+            .fold(TestRequest::default(), |request, (name, value)| request.header(*name, *value))
+            .to_http_request();
+
+        match check_conditions(&request, "abcd") {
+            Condition::Status(status) => Err(status),
+            Condition::Content { whole } => Ok(whole),
+        }
+    };
+
+    assert_eq!(condition(&[]), Ok(false));
+    assert_eq!(condition(&[("If-None-Match", "\"abcd\"")]), Err(StatusCode::NOT_MODIFIED));
+    assert_eq!(condition(&[("If-None-Match", "\"ef01\"")]), Ok(false));
+    assert_eq!(condition(&[("If-Match", "\"ef01\"")]), Err(StatusCode::PRECONDITION_FAILED));
+    assert_eq!(condition(&[("Range", "bytes=0-9"), ("If-Range", "\"abcd\"")]), Ok(false));
+    assert_eq!(condition(&[("Range", "bytes=0-9"), ("If-Range", "\"ef01\"")]), Ok(true));
+}
@@ -1,3 +1,6 @@
+use std::net::IpAddr;
+use std::str::FromStr;
+
 use rand::Rng;
 
 fn random_number(min: u32, max: u32) -> u32 {
@@ -20,14 +23,118 @@ pub fn generate_random_link() -> String {
     link
 }
 
+/// Random alphanumeric secret, e.g. for csrf tokens.
+pub fn generate_token() -> String {
+    rand::thread_rng()
+        .sample_iter(&rand::distributions::Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect()
+}
+
 pub fn is_link_valid<T: AsRef<str>>(link: T) -> bool {
     for x in link.as_ref().chars() {
-        if ! ((x >= 'A' && x <= 'Z') || (x >= 'a' && x <= 'z')) {
+        if !x.is_ascii_alphabetic() {
             return false;
         }
     }
 
-    return true;
+    true
+}
+
+/// Parses a human readable duration such as `12h` or `7d`; `never` (or `0`) means no duration.
+pub fn parse_duration<T: AsRef<str>>(value: T) -> Result<Option<std::time::Duration>, String> {
+    match value.as_ref().trim() {
+        "never" | "0" => Ok(None),
+        other => humantime::parse_duration(other)
+            .map(Some)
+            .map_err(|e| format!("Invalid duration {}: {}", other, e)),
+    }
+}
+
+/// Parses a size such as `500M` or `10G` (powers of 1024); `unlimited` (or `0`) means no limit.
+pub fn parse_size<T: AsRef<str>>(value: T) -> Result<Option<u64>, String> {
+    let value = value.as_ref().trim();
+
+    if value == "unlimited" || value == "0" {
+        return Ok(None);
+    }
+
+    let upper = value.to_ascii_uppercase();
+    let number = upper.strip_suffix('B').unwrap_or(&upper);
+
+    let (number, unit) = match number.chars().last() {
+        Some('K') => (&number[..number.len() - 1], 1u64 << 10),
+        Some('M') => (&number[..number.len() - 1], 1 << 20),
+        Some('G') => (&number[..number.len() - 1], 1 << 30),
+        Some('T') => (&number[..number.len() - 1], 1 << 40),
+        _ => (number, 1),
+    };
+
+    number.trim()
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(unit))
+        .map(Some)
+        .ok_or_else(|| format!("Invalid size {}", value))
+}
+
+/// Percent-encodes everything but unreserved characters, e.g. for a query parameter.
+pub fn percent_encode(value: &str) -> String {
+    value.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// An ip network such as `10.0.0.0/8`, a single address when written without a prefix length.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct IpNetwork {
+    address: IpAddr,
+    prefix: u8,
+}
+
+impl IpNetwork {
+    pub fn contains(&self, address: &IpAddr) -> bool {
+        match (self.address, address) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+
+                u32::from(network) & mask == u32::from(*address) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+
+                u128::from(network) & mask == u128::from(*address) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpNetwork {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid network {}", value);
+
+        let (address, prefix) = match value.trim().split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (value.trim(), None),
+        };
+
+        let address = IpAddr::from_str(address).map_err(|_| invalid())?;
+        let max_prefix = if address.is_ipv4() { 32 } else { 128 };
+
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse::<u8>().ok().filter(|prefix| *prefix <= max_prefix).ok_or_else(invalid)?,
+            None => max_prefix,
+        };
+
+        Ok(IpNetwork { address, prefix })
+    }
 }
 
 #[test]
@@ -38,3 +145,34 @@ fn gen_links_works() {
         "Link between 10 and 15 characters long"
     );
 }
+
+#[test]
+fn parse_duration_works() {
+    assert_eq!(parse_duration("7d"), Ok(Some(std::time::Duration::from_secs(7 * 24 * 3600))));
+    assert_eq!(parse_duration("never"), Ok(None));
+    assert!(parse_duration("soon").is_err());
+}
+
+#[test]
+fn parse_size_works() {
+    assert_eq!(parse_size("1024"), Ok(Some(1024)));
+    assert_eq!(parse_size("500M"), Ok(Some(500 * 1024 * 1024)));
+    assert_eq!(parse_size("10GB"), Ok(Some(10 * 1024 * 1024 * 1024)));
+    assert_eq!(parse_size("2k"), Ok(Some(2048)));
+    assert_eq!(parse_size("unlimited"), Ok(None));
+    assert!(parse_size("ten").is_err());
+}
+
+#[test]
+fn ip_network_works() {
+    let network = IpNetwork::from_str("10.1.0.0/16").unwrap();
+
+    assert!(network.contains(&"10.1.200.3".parse().unwrap()));
+    assert!(!network.contains(&"10.2.0.1".parse().unwrap()));
+    assert!(!network.contains(&"::1".parse().unwrap()));
+    assert!(IpNetwork::from_str("0.0.0.0/0").unwrap().contains(&"192.168.1.1".parse().unwrap()));
+    assert!(IpNetwork::from_str("::1").unwrap().contains(&"::1".parse().unwrap()));
+    assert!(IpNetwork::from_str("fd00::/8").unwrap().contains(&"fd12::1".parse().unwrap()));
+    assert!(IpNetwork::from_str("10.0.0.0/33").is_err());
+    assert!(IpNetwork::from_str("localhost").is_err());
+}
@@ -0,0 +1,303 @@
+use std::collections::VecDeque;
+use std::io::{Cursor, Read};
+use std::sync::Arc;
+
+use crate::storage::StorageBackend;
+
+/// Sizes and offsets from this value on are moved to zip64 extra fields.
+const ZIP64_LIMIT: u64 = 0xFFFF_FFFF;
+
+const VERSION_DEFAULT: u16 = 20;
+const VERSION_ZIP64: u16 = 45;
+
+/// Names are utf-8.
+const FLAG_UTF8: u16 = 0x0800;
+
+/// An entry of a streamed archive.
+#[derive(Clone, Debug)]
+pub struct ZipEntry {
+    pub name: String,
+    pub source: ZipSource,
+    pub size: u64,
+    pub crc32: u32,
+}
+
+#[derive(Clone, Debug)]
+pub enum ZipSource {
+    Directory,
+    /// Content of a storage object, by key.
+    Object(String),
+    Bytes(Vec<u8>),
+}
+
+impl ZipEntry {
+    pub fn directory(name: String) -> Self {
+        ZipEntry { name, source: ZipSource::Directory, size: 0, crc32: 0 }
+    }
+
+    pub fn bytes(name: String, content: Vec<u8>) -> Self {
+        ZipEntry { name, size: content.len() as u64, crc32: crc32fast::hash(&content), source: ZipSource::Bytes(content) }
+    }
+}
+
+enum Part {
+    Bytes(Vec<u8>),
+    Object(String),
+}
+
+/// Zip archive written while it is read, from objects whose sizes and checksums are known upfront.
+///
+/// Entries are stored without compression, so the size of the whole archive is known before sending it.
+pub struct ZipStream {
+    storage: Arc<dyn StorageBackend>,
+    parts: VecDeque<Part>,
+    current: Option<Box<dyn Read + Send>>,
+    size: u64,
+}
+
+impl ZipStream {
+    /// `modified` is the unix time given to every entry.
+    pub fn new(storage: Arc<dyn StorageBackend>, entries: &[ZipEntry], modified: u64) -> Self {
+        let (time, date) = dos_date_time(modified);
+
+        let mut parts = VecDeque::new();
+        let mut central_directory = Vec::new();
+        let mut offset: u64 = 0;
+
+        for entry in entries {
+            let is_zip64 = entry.size >= ZIP64_LIMIT;
+            let version = if is_zip64 || offset >= ZIP64_LIMIT { VERSION_ZIP64 } else { VERSION_DEFAULT };
+            let size32 = if is_zip64 { ZIP64_LIMIT as u32 } else { entry.size as u32 };
+
+            let mut header = Vec::new();
+
+            put_u32(&mut header, 0x0403_4b50);
+            put_u16(&mut header, version);
+            put_u16(&mut header, FLAG_UTF8);
+            put_u16(&mut header, 0);
+            put_u16(&mut header, time);
+            put_u16(&mut header, date);
+            put_u32(&mut header, entry.crc32);
+            put_u32(&mut header, size32);
+            put_u32(&mut header, size32);
+            put_u16(&mut header, entry.name.len() as u16);
+            put_u16(&mut header, if is_zip64 { 20 } else { 0 });
+            header.extend_from_slice(entry.name.as_bytes());
+
+            if is_zip64 {
+                put_u16(&mut header, 0x0001);
+                put_u16(&mut header, 16);
+                put_u64(&mut header, entry.size);
+                put_u64(&mut header, entry.size);
+            }
+
+            let mut extra = Vec::new();
+
+            if is_zip64 {
+                put_u64(&mut extra, entry.size);
+                put_u64(&mut extra, entry.size);
+            }
+
+            if offset >= ZIP64_LIMIT {
+                put_u64(&mut extra, offset);
+            }
+
+            let external_attributes = match entry.source {
+                ZipSource::Directory => (0o040755 << 16) | 0x10,
+                _ => 0o100644 << 16,
+            };
+
+            put_u32(&mut central_directory, 0x0201_4b50);
+            put_u16(&mut central_directory, (3 << 8) | version);
+            put_u16(&mut central_directory, version);
+            put_u16(&mut central_directory, FLAG_UTF8);
+            put_u16(&mut central_directory, 0);
+            put_u16(&mut central_directory, time);
+            put_u16(&mut central_directory, date);
+            put_u32(&mut central_directory, entry.crc32);
+            put_u32(&mut central_directory, size32);
+            put_u32(&mut central_directory, size32);
+            put_u16(&mut central_directory, entry.name.len() as u16);
+            put_u16(&mut central_directory, if extra.is_empty() { 0 } else { extra.len() as u16 + 4 });
+            put_u16(&mut central_directory, 0);
+            put_u16(&mut central_directory, 0);
+            put_u16(&mut central_directory, 0);
+            put_u32(&mut central_directory, external_attributes);
+            put_u32(&mut central_directory, offset.min(ZIP64_LIMIT) as u32);
+            central_directory.extend_from_slice(entry.name.as_bytes());
+
+            if !extra.is_empty() {
+                put_u16(&mut central_directory, 0x0001);
+                put_u16(&mut central_directory, extra.len() as u16);
+                central_directory.extend_from_slice(&extra);
+            }
+
+            offset += header.len() as u64;
+            parts.push_back(Part::Bytes(header));
+
+            offset += entry.size;
+
+            match entry.source {
+                ZipSource::Directory => {}
+                ZipSource::Object(ref key) => parts.push_back(Part::Object(key.clone())),
+                ZipSource::Bytes(ref content) => parts.push_back(Part::Bytes(content.clone())),
+            }
+        }
+
+        let count = entries.len() as u64;
+        let directory_size = central_directory.len() as u64;
+        let mut end = central_directory;
+
+        if count >= 0xFFFF || offset >= ZIP64_LIMIT || directory_size >= ZIP64_LIMIT {
+            let zip64_end_offset = offset + directory_size;
+
+            put_u32(&mut end, 0x0606_4b50);
+            put_u64(&mut end, 44);
+            put_u16(&mut end, VERSION_ZIP64);
+            put_u16(&mut end, VERSION_ZIP64);
+            put_u32(&mut end, 0);
+            put_u32(&mut end, 0);
+            put_u64(&mut end, count);
+            put_u64(&mut end, count);
+            put_u64(&mut end, directory_size);
+            put_u64(&mut end, offset);
+
+            put_u32(&mut end, 0x0706_4b50);
+            put_u32(&mut end, 0);
+            put_u64(&mut end, zip64_end_offset);
+            put_u32(&mut end, 1);
+        }
+
+        put_u32(&mut end, 0x0605_4b50);
+        put_u16(&mut end, 0);
+        put_u16(&mut end, 0);
+        put_u16(&mut end, count.min(0xFFFF) as u16);
+        put_u16(&mut end, count.min(0xFFFF) as u16);
+        put_u32(&mut end, directory_size.min(ZIP64_LIMIT) as u32);
+        put_u32(&mut end, offset.min(ZIP64_LIMIT) as u32);
+        put_u16(&mut end, 0);
+
+        let size = offset + end.len() as u64;
+
+        parts.push_back(Part::Bytes(end));
+
+        ZipStream { storage, parts, current: None, size }
+    }
+
+    /// Length of the whole archive.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+impl Read for ZipStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            if let Some(ref mut current) = self.current {
+                let read = current.read(buf)?;
+
+                if read > 0 || buf.is_empty() {
+                    return Ok(read);
+                }
+
+                self.current = None;
+            }
+
+            self.current = match self.parts.pop_front() {
+                Some(Part::Bytes(bytes)) => Some(Box::new(Cursor::new(bytes))),
+                Some(Part::Object(key)) => Some(self.storage
+                    .get(&key)
+                    .map_err(|e| std::io::Error::other(e.to_string()))?),
+                None => return Ok(0),
+            };
+        }
+    }
+}
+
+fn put_u16(buffer: &mut Vec<u8>, value: u16) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(buffer: &mut Vec<u8>, value: u32) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u64(buffer: &mut Vec<u8>, value: u64) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+/// MS-DOS time and date of a unix time, in UTC as zip has no time zone.
+fn dos_date_time(timestamp: u64) -> (u16, u16) {
+    let days = (timestamp / 86400) as i64;
+    let seconds = timestamp % 86400;
+
+    // Civil date from days since 1970-01-01, after Howard Hinnant's algorithm.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    if !(1980..=2107).contains(&year) {
+        return (0, (1 << 5) | 1);
+    }
+
+    let time = ((seconds / 3600) << 11) | (((seconds % 3600) / 60) << 5) | ((seconds % 60) / 2);
+    let date = ((year - 1980) << 9) | (month << 5) | day;
+
+    (time as u16, date as u16)
+}
+
+#[test]
+fn zip_stream_works() {
+    use crate::storage::LocalStorage;
+
+    let directory = std::env::temp_dir().join(format!("fshare-zip-stream-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+
+    let storage = LocalStorage::new(directory.clone());
+
+    let mut entries = vec![ZipEntry::directory("docs/".to_string())];
+
+    for (name, content) in [("docs/a.txt", "first file"), ("b.txt", "second file")] {
+        storage.put(name.replace('/', "-").as_str(), &mut content.as_bytes(), content.len() as u64).unwrap();
+
+        entries.push(ZipEntry {
+            name: name.to_string(),
+            source: ZipSource::Object(name.replace('/', "-")),
+            size: content.len() as u64,
+            crc32: crc32fast::hash(content.as_bytes()),
+        });
+    }
+
+    entries.push(ZipEntry::bytes("SHA256SUMS".to_string(), b"sums".to_vec()));
+
+    let mut stream = ZipStream::new(Arc::new(storage), &entries, 1_700_000_000);
+
+    let mut archive = Vec::new();
+    stream.read_to_end(&mut archive).unwrap();
+
+    assert_eq!(archive.len() as u64, stream.size());
+
+    let mut archive = zip::ZipArchive::new(Cursor::new(archive)).unwrap();
+
+    assert_eq!(archive.len(), 4);
+    assert!(archive.by_name("docs/").unwrap().is_dir());
+
+    let mut content = String::new();
+    archive.by_name("docs/a.txt").unwrap().read_to_string(&mut content).unwrap();
+
+    assert_eq!(content, "first file");
+
+    content.clear();
+    archive.by_name("SHA256SUMS").unwrap().read_to_string(&mut content).unwrap();
+
+    assert_eq!(content, "sums");
+    assert_eq!(archive.by_name("b.txt").unwrap().last_modified().year(), 2023);
+
+    std::fs::remove_dir_all(directory).unwrap();
+}
@@ -65,9 +65,40 @@ function humanFileSize(size) {
     return (size / Math.pow(1000, i)).toFixed(2) * 1 + ' ' + ['B', 'kB', 'MB', 'GB', 'TB'][i];
 }
 
+/** Value of an optional form input, upload request pages leave some out. */
+function optionalValue(id) {
+    const input = document.getElementById(id);
+
+    return input ? input.value : '';
+}
+
 function send() {
     const payload = new FormData();
 
+    const expiresIn = optionalValue('expires_in');
+
+    if (expiresIn) {
+        payload.append('expires_in', expiresIn);
+    }
+
+    const maxDownloads = optionalValue('max_downloads');
+
+    if (maxDownloads) {
+        payload.append('max_downloads', maxDownloads);
+    }
+
+    const message = optionalValue('message');
+
+    if (message) {
+        payload.append('message', message);
+    }
+
+    const password = optionalValue('password');
+
+    if (password) {
+        payload.append('password', password);
+    }
+
     for (const [name, file] of filesToSend.entries()) {
         payload.append(name, file);
     }
@@ -88,7 +119,7 @@ function send() {
     ajax.addEventListener("error", errorHandler, false);
     ajax.addEventListener("abort", abortHandler, false);
 
-    ajax.open("POST", "/");
+    ajax.open("POST", document.getElementById('upload-form').getAttribute('action'));
     ajax.send(payload);
 }
 